@@ -0,0 +1,349 @@
+use std::fmt;
+
+
+// Channels are stored as fractions between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+    pub alpha: f64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsl {
+    pub hue: f64,
+    pub saturation: f64,
+    pub lightness: f64
+}
+
+
+impl Color {
+    // Accepts `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `0xaarrggbb`,
+    // `rgb(r, g, b)`, `rgba(r, g, b, a)`, `hsl(h, s%, l%)` and
+    // `hsla(h, s%, l%, a)`.
+    pub fn parse(input: &str) -> Result<Color, String> {
+        let input = input.trim();
+        let lower = input.to_lowercase();
+
+        if let Some(hex) = lower.strip_prefix('#') {
+            return Color::parse_hex(hex, false)
+                .ok_or_else(|| format!("invalid hex color '{}'", input));
+        }
+        if let Some(hex) = lower.strip_prefix("0x") {
+            return Color::parse_hex(hex, true)
+                .ok_or_else(|| format!("invalid hex color '{}'", input));
+        }
+
+        let open = lower.find('(');
+        let (name, arguments) = match (open, lower.strip_suffix(')')) {
+            (Some(open), Some(inner)) => (&lower[..open], &inner[open + 1..]),
+            _ => return Err(format!("unrecognized color '{}'", input))
+        };
+        let arguments: Vec<&str> = arguments.split(',')
+            .map(str::trim)
+            .collect();
+
+        let alpha = match (name, arguments.len()) {
+            ("rgb", 3) | ("hsl", 3) => 1.0,
+            ("rgba", 4) | ("hsla", 4) => Color::number(arguments[3], 1.0)?,
+            _ => return Err(format!("unrecognized color '{}'", input))
+        };
+
+        let color = if name.starts_with("rgb") {
+            Color {
+                red: Color::number(arguments[0], 255.0)?,
+                green: Color::number(arguments[1], 255.0)?,
+                blue: Color::number(arguments[2], 255.0)?,
+                alpha
+            }
+        }
+        else {
+            let hue = arguments[0].trim_end_matches("deg");
+            let hue = match hue.parse::<f64>() {
+                Ok(h) => h,
+                Err(_) => return Err(format!(
+                    "invalid hue '{}' in '{}'", arguments[0], input))
+            };
+            Hsl {
+                hue,
+                saturation: Color::number(arguments[1], 100.0)?,
+                lightness: Color::number(arguments[2], 100.0)?
+            }.to_color(alpha)
+        };
+
+        Ok(color.clamp())
+    }
+
+    fn parse_hex(hex: &str, alpha_first: bool) -> Option<Color> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let digits: Vec<f64> = match hex.len() {
+            3 | 4 => hex.chars()
+                .map(|c| c.to_digit(16).unwrap() as f64 * 17.0 / 255.0)
+                .collect(),
+            6 | 8 => (0..hex.len() / 2)
+                .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                    .unwrap() as f64 / 255.0)
+                .collect(),
+            _ => return None
+        };
+
+        let (rgb, alpha) = match (digits.len(), alpha_first) {
+            (3, _) => (&digits[..], 1.0),
+            (4, true) => (&digits[1..], digits[0]),
+            (4, false) => (&digits[..3], digits[3]),
+            _ => unreachable!()
+        };
+
+        Some(Color { red: rgb[0], green: rgb[1], blue: rgb[2], alpha })
+    }
+
+    // Parses a channel given either as a percentage or as a number out of
+    // `scale`.
+    fn number(input: &str, scale: f64) -> Result<f64, String> {
+        let (number, scale) = match input.strip_suffix('%') {
+            Some(percent) => (percent, 100.0),
+            None => (input, scale)
+        };
+        match number.trim().parse::<f64>() {
+            Ok(n) => Ok(n / scale),
+            Err(_) => Err(format!("invalid color channel '{}'", input))
+        }
+    }
+
+    fn clamp(self) -> Color {
+        Color {
+            red: clamp(self.red),
+            green: clamp(self.green),
+            blue: clamp(self.blue),
+            alpha: clamp(self.alpha)
+        }
+    }
+
+    pub fn to_hsl(self) -> Hsl {
+        let max = self.red.max(self.green).max(self.blue);
+        let min = self.red.min(self.green).min(self.blue);
+        let lightness = (max + min) / 2.0;
+        let delta = max - min;
+
+        if delta == 0.0 {
+            return Hsl { hue: 0.0, saturation: 0.0, lightness };
+        }
+
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == self.red {
+            ((self.green - self.blue) / delta).rem_euclid(6.0)
+        }
+        else if max == self.green {
+            (self.blue - self.red) / delta + 2.0
+        }
+        else {
+            (self.red - self.green) / delta + 4.0
+        };
+
+        Hsl { hue: hue * 60.0, saturation, lightness }
+    }
+
+    // WCAG relative luminance.
+    pub fn luminance(&self) -> f64 {
+        let linear = |c: f64| if c <= 0.03928 {
+            c / 12.92
+        }
+        else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        };
+        0.2126 * linear(self.red) + 0.7152 * linear(self.green)
+            + 0.0722 * linear(self.blue)
+    }
+
+    pub fn contrast(&self, other: &Color) -> f64 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    pub fn lighten(&self, amount: f64) -> Color {
+        let mut hsl = self.to_hsl();
+        hsl.lightness = clamp(hsl.lightness + amount);
+        hsl.to_color(self.alpha)
+    }
+
+    pub fn saturate(&self, amount: f64) -> Color {
+        let mut hsl = self.to_hsl();
+        hsl.saturation = clamp(hsl.saturation + amount);
+        hsl.to_color(self.alpha)
+    }
+
+    pub fn with_alpha(&self, alpha: f64) -> Color {
+        Color { alpha: clamp(alpha), ..*self }
+    }
+
+    pub fn invert(&self) -> Color {
+        Color {
+            red: 1.0 - self.red,
+            green: 1.0 - self.green,
+            blue: 1.0 - self.blue,
+            alpha: self.alpha
+        }
+    }
+
+    // Mixes in `weight` of the other color.
+    pub fn mix(&self, other: &Color, weight: f64) -> Color {
+        let weight = clamp(weight);
+        let mix = |a: f64, b: f64| a * (1.0 - weight) + b * weight;
+        Color {
+            red: mix(self.red, other.red),
+            green: mix(self.green, other.green),
+            blue: mix(self.blue, other.blue),
+            alpha: mix(self.alpha, other.alpha)
+        }
+    }
+
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}",
+            byte(self.red), byte(self.green), byte(self.blue))
+    }
+
+    pub fn hexa(&self) -> String {
+        format!("{}{:02x}", self.hex(), byte(self.alpha))
+    }
+
+    pub fn argb(&self) -> String {
+        format!("#{:02x}{}", byte(self.alpha), &self.hex()[1..])
+    }
+
+    pub fn rgb(&self) -> String {
+        format!("rgb({}, {}, {})",
+            byte(self.red), byte(self.green), byte(self.blue))
+    }
+
+    pub fn rgba(&self) -> String {
+        format!("rgba({}, {}, {}, {})",
+            byte(self.red), byte(self.green), byte(self.blue),
+            decimal(self.alpha))
+    }
+
+    pub fn hsl(&self) -> String {
+        let hsl = self.to_hsl();
+        format!("hsl({}, {}%, {}%)",
+            hsl.hue.round(),
+            (hsl.saturation * 100.0).round(),
+            (hsl.lightness * 100.0).round())
+    }
+
+    pub fn rgb_float(&self) -> String {
+        format!("{},{},{}",
+            decimal(self.red), decimal(self.green), decimal(self.blue))
+    }
+}
+
+// Colors are written back as hex, with an alpha channel only when they are
+// not opaque.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if byte(self.alpha) == 255 {
+            write!(f, "{}", self.hex())
+        }
+        else {
+            write!(f, "{}", self.hexa())
+        }
+    }
+}
+
+impl Hsl {
+    pub fn to_color(self, alpha: f64) -> Color {
+        let chroma = (1.0 - (2.0 * self.lightness - 1.0).abs())
+            * self.saturation;
+        let hue = self.hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (red, green, blue) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x)
+        };
+        let m = self.lightness - chroma / 2.0;
+
+        Color {
+            red: red + m,
+            green: green + m,
+            blue: blue + m,
+            alpha
+        }.clamp()
+    }
+}
+
+fn clamp(value: f64) -> f64 {
+    value.clamp(0.0, 1.0)
+}
+
+fn byte(value: f64) -> u8 {
+    (clamp(value) * 255.0).round() as u8
+}
+
+// Formats with at most three decimals and without trailing zeros.
+fn decimal(value: f64) -> String {
+    let formatted = format!("{:.3}", value);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let color = Color::parse("#ff8000").unwrap();
+
+        assert_eq!(Color::parse("#FF8000").unwrap(), color);
+        assert_eq!(Color::parse("#f80").unwrap().hex(), "#ff8800");
+        assert_eq!(Color::parse("0x80ff8000").unwrap().alpha, 128.0 / 255.0);
+        assert_eq!(Color::parse("#ff800080").unwrap().hexa(), "#ff800080");
+        assert_eq!(Color::parse("rgb(255, 128, 0)").unwrap(), color);
+        assert_eq!(Color::parse("rgba(100%, 50%, 0%, 0.5)").unwrap().rgba(),
+            "rgba(255, 128, 0, 0.5)");
+        assert_eq!(Color::parse("hsl(30, 100%, 50%)").unwrap().hex(),
+            "#ff8000");
+
+        assert!(Color::parse("#ff80").is_ok());
+        assert!(Color::parse("#ff8").is_ok());
+        assert!(Color::parse("#ff80001").is_err());
+        assert!(Color::parse("#gg8000").is_err());
+        assert!(Color::parse("rgb(1, 2)").is_err());
+        assert!(Color::parse("orange").is_err());
+    }
+
+    #[test]
+    fn test_formats() {
+        let color = Color::parse("#336699").unwrap();
+
+        assert_eq!(color.hex(), "#336699");
+        assert_eq!(color.hexa(), "#336699ff");
+        assert_eq!(color.argb(), "#ff336699");
+        assert_eq!(color.rgb(), "rgb(51, 102, 153)");
+        assert_eq!(color.rgba(), "rgba(51, 102, 153, 1)");
+        assert_eq!(color.hsl(), "hsl(210, 50%, 40%)");
+        assert_eq!(color.rgb_float(), "0.2,0.4,0.6");
+        assert_eq!(color.with_alpha(0.5).to_string(), "#33669980");
+    }
+
+    #[test]
+    fn test_transformations() {
+        let color = Color::parse("#336699").unwrap();
+        let white = Color::parse("#ffffff").unwrap();
+        let black = Color::parse("#000000").unwrap();
+
+        assert_eq!(color.lighten(0.2).hex(), "#6699cc");
+        assert_eq!(color.lighten(-0.2).hex(), "#1a334d");
+        assert_eq!(color.saturate(-0.5).hex(), "#666666");
+        assert_eq!(color.invert().hex(), "#cc9966");
+        assert_eq!(black.mix(&white, 0.5).hex(), "#808080");
+        assert_eq!(black.mix(&white, 0.0).hex(), "#000000");
+        assert_eq!(black.contrast(&white).round(), 21.0);
+        assert!(color.contrast(&white) > color.contrast(&black));
+    }
+}
@@ -1,84 +1,539 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
-use std::error::Error;
 use std::env;
+use std::fs;
 use std::fs::File;
+use std::io;
 
 use serde_yaml;
 
+use variables::Value;
+use template::Delimiters;
+use glob::Pattern;
+
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub bindings: Vec<PathBinding>,
-    pub variables: HashMap<String, String>
+    pub variables: HashMap<String, Value>,
+    // The keys of variables read from the environment, whose values are
+    // taken as they are rather than resolved.
+    #[serde(skip)]
+    pub literals: HashSet<String>,
+    // Every file the variables were read from, extended ones included.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+    // The patterns of bindings from a directory or a glob, already expanded
+    // in `bindings`.
+    #[serde(skip)]
+    pub patterns: Vec<Pattern>
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PathBinding {
     pub from: PathBuf,
     pub to: PathBuf,
+    #[serde(default)]
+    pub delimiters: Option<Delimiters>,
+    // Whether the binding comes from a directory or a glob.
+    #[serde(skip)]
+    pub expanded: bool
+}
+
+// The bindings file is either a bare list of bindings, or a map holding the
+// list along with defaults for every binding and the environment variables
+// templates may use. A combined config file is such a map, with a
+// `variables` section as well.
+#[derive(Debug, Deserialize)]
+struct BindingsFile {
+    bindings: Vec<PathBinding>,
+    #[serde(default)]
+    delimiters: Option<Delimiters>,
+    #[serde(default)]
+    environment: Vec<String>,
+    #[serde(skip)]
+    patterns: Vec<Pattern>
 }
 
+// Variables files are layers, each one overriding the previous ones. The
+// bindings file may be one of them, when it has a `variables` section. The
+// profile file holds the name of the active profile, if any.
 pub struct ConfigFiles<'a> {
     pub bindings: &'a Path,
-    pub variables: &'a Path
+    pub variables: Vec<&'a Path>,
+    pub profile: Option<&'a Path>
+}
+
+// Every layer of variables merged in order, along with the files they were
+// read from and the names of the profiles they define.
+struct Layers {
+    variables: HashMap<String, Value>,
+    sources: Vec<PathBuf>,
+    profiles: BTreeSet<String>
 }
 
+const EXTENDS: &str = "extends";
+const PROFILES: &str = "profiles";
+const ENV: &str = "env";
+
+
+impl<'a> ConfigFiles<'a> {
+    // A single file holding both the `bindings` and `variables` sections.
+    pub fn combined(path: &'a Path) -> ConfigFiles<'a> {
+        ConfigFiles {
+            bindings: path,
+            variables: vec![path],
+            profile: None
+        }
+    }
+}
 
 impl Config {
-    pub fn new(config_files: &ConfigFiles) -> Result<Config, String>{
-        let bindings_file = match File::open(config_files.bindings) {
+    pub fn new(config_files: &ConfigFiles) -> Result<Config, String> {
+        Config::load(config_files, &|name| env::var(name).ok())
+    }
+
+    // Reads the config, with `lookup` giving the value of environment
+    // variables.
+    fn load(config_files: &ConfigFiles,
+        lookup: &dyn Fn(&str) -> Option<String>) -> Result<Config, String>
+    {
+        let document = Config::read(config_files.bindings)?;
+        let profile = match config_files.profile {
+            Some(path) => Config::active_profile(path)?,
+            None => None
+        };
+        let layers = Config::layers(config_files, &document,
+            profile.as_deref())?;
+        let bindings_file
+            = Config::parse_bindings(document, config_files.bindings)?;
+        let mut sources = layers.sources;
+        if let Some(path) = config_files.profile {
+            sources.push(path.to_path_buf());
+        }
+
+        // A profile since removed from the config is not worth failing for,
+        // the shared variables are still there.
+        let checked = Config::check_profile(profile.as_deref(),
+            &layers.profiles);
+        if let Err(e) = checked {
+            warn!("{}", e);
+        }
+
+        // Allowed environment variables come first, so that any variable
+        // of the same name overrides them.
+        let environment = Config::environment(&bindings_file.environment,
+            lookup);
+        let defined = layers.variables.get(ENV);
+        let literals = environment.keys()
+            .filter(|name| defined.and_then(|env| env.get(name)).is_none())
+            .map(|name| format!("{}.{}", ENV, name))
+            .collect();
+        let mut variables = HashMap::new();
+        if !environment.is_empty() {
+            variables.insert(ENV.to_string(), Value::Map(environment));
+        }
+        Config::merge(&mut variables, layers.variables);
+
+        Ok(Config {
+            bindings: bindings_file.bindings,
+            variables,
+            literals,
+            sources,
+            patterns: bindings_file.patterns
+        })
+    }
+
+    // Makes `name` the active profile, once it is known to exist.
+    pub fn set_profile(config_files: &ConfigFiles, name: &str)
+        -> Result<(), String>
+    {
+        let path = match config_files.profile {
+            Some(path) => path,
+            None => return Err(
+                "Couldn't find where to store the active profile".to_string())
+        };
+        let document = Config::read(config_files.bindings)?;
+        let layers = Config::layers(config_files, &document, Some(name))?;
+        Config::check_profile(Some(name), &layers.profiles)?;
+
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(format!("Couldn't create {}: {}",
+                    dir.display(), e));
+            }
+        }
+        match fs::write(path, format!("{}\n", name)) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Couldn't write {}: {}", path.display(), e))
+        }
+    }
+
+    // Where the active profile of the config file `path` is kept, named
+    // after its canonical path so that every config has its own.
+    pub fn profile_file(path: &Path) -> Option<PathBuf> {
+        let state = match env::var_os("XDG_STATE_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/state")
+        };
+        let name = Config::canonical(path).to_string_lossy().replace('/', "%");
+        Some(state.join("confy/profiles").join(name))
+    }
+
+    // The given environment variables which are set, to become the fields
+    // of `env`.
+    fn environment(names: &[String], lookup: &dyn Fn(&str) -> Option<String>)
+        -> BTreeMap<String, Value>
+    {
+        names.iter()
+            .filter_map(|name| lookup(name)
+                .map(|value| (name.clone(), Value::String(value))))
+            .collect()
+    }
+
+    fn active_profile(path: &Path) -> Result<Option<String>, String> {
+        match fs::read_to_string(path) {
+            Ok(name) => {
+                let name = name.trim();
+                Ok(if name.is_empty() { None } else { Some(name.to_string()) })
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Couldn't read {}: {}", path.display(), e))
+        }
+    }
+
+    // Merges the variables of the profile `name` over the shared ones of a
+    // layer, and adds the profiles it defines to `names`.
+    fn apply_profile(layer: &mut HashMap<String, Value>, name: Option<&str>,
+        names: &mut BTreeSet<String>) -> Result<(), String>
+    {
+        let profiles = match layer.remove(PROFILES) {
+            Some(Value::Map(profiles)) => profiles,
+            Some(Value::Null) | None => return Ok(()),
+            Some(_) => return Err(format!(
+                "Invalid {}: expected a map of profile names to variables",
+                PROFILES))
+        };
+        names.extend(profiles.keys().cloned());
+
+        match name.and_then(|name| profiles.get(name)) {
+            Some(Value::Map(fields)) => {
+                let fields = fields.iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                Config::merge(layer, Config::expand(fields));
+                Ok(())
+            },
+            Some(Value::Null) | None => Ok(()),
+            Some(_) => Err(format!(
+                "Invalid profile '{}': expected a map of variables",
+                name.unwrap_or_default()))
+        }
+    }
+
+    fn check_profile(name: Option<&str>, names: &BTreeSet<String>)
+        -> Result<(), String>
+    {
+        match name {
+            Some(name) if names.is_empty() => Err(format!(
+                "Unknown profile '{}', the config defines no profiles", name)),
+            Some(name) if !names.contains(name) =>
+                Err(format!("Unknown profile '{}', expected one of: {}",
+                    name, names.iter()
+                        .map(|key| &key[..])
+                        .collect::<Vec<&str>>()
+                        .join(", "))),
+            _ => Ok(())
+        }
+    }
+
+    // The profile `name` applies to each layer in turn, so that later
+    // layers still override it.
+    fn layers(config_files: &ConfigFiles, document: &serde_yaml::Value,
+        name: Option<&str>) -> Result<Layers, String>
+    {
+        let mut variables = HashMap::new();
+        let mut sources = Vec::new();
+        let mut profiles = BTreeSet::new();
+        for path in config_files.variables.iter() {
+            let layer = if *path == config_files.bindings {
+                match document.get("variables") {
+                    Some(layer) => layer.clone(),
+                    None => continue
+                }
+            }
+            else {
+                Config::read(path)?
+            };
+            let mut stack = vec![Config::canonical(path)];
+            let mut layer = Config::load_variables(layer, path, &mut stack,
+                &mut sources)?;
+            Config::apply_profile(&mut layer, name, &mut profiles)?;
+            Config::merge(&mut variables, layer);
+        }
+
+        Ok(Layers { variables, sources, profiles })
+    }
+
+    fn read(path: &Path) -> Result<serde_yaml::Value, String> {
+        let file = match File::open(path) {
             Ok(f) => f,
             Err(e) => return Err(format!(
                 "Couldn't open {}: {}",
-                config_files.bindings.display(),
-                e.description()
+                path.display(),
+                e
             ))
         };
 
-        let mut bindings: Vec<PathBinding>
-            = match serde_yaml::from_reader(bindings_file) {
-            Ok(c) => c,
+        match serde_yaml::from_reader(file) {
+            Ok(document) => Ok(document),
+            Err(e) => Err(format!(
+                "Couldn't parse {}: {}",
+                path.display(),
+                e
+            ))
+        }
+    }
+
+    fn parse_bindings(document: serde_yaml::Value, path: &Path)
+        -> Result<BindingsFile, String>
+    {
+        let parsed = if document.is_sequence() {
+            serde_yaml::from_value(document).map(|bindings| BindingsFile {
+                bindings,
+                delimiters: None,
+                environment: Vec::new(),
+                patterns: Vec::new()
+            })
+        }
+        else {
+            serde_yaml::from_value(document)
+        };
+        let mut bindings_file: BindingsFile = match parsed {
+            Ok(b) => b,
             Err(e) => return Err(format!(
                 "Couldn't parse {}: {}",
-                config_files.bindings.display(),
-                e.description()
+                path.display(),
+                e
             ))
         };
 
-        let config_dir = config_files.bindings.parent();
-        for binding in bindings.iter_mut() {
+        let config_dir = path.parent();
+        let mut bindings = Vec::with_capacity(bindings_file.bindings.len());
+        for mut binding in bindings_file.bindings.drain(..) {
             binding.from = Config::resolve_path(&binding.from, config_dir);
             binding.to = Config::resolve_path(&binding.to, config_dir);
+            if binding.delimiters.is_none() {
+                binding.delimiters = bindings_file.delimiters.clone();
+            }
+            if let Some(ref delimiters) = binding.delimiters {
+                if let Err(e) = delimiters.validate() {
+                    return Err(format!(
+                        "Invalid binding {} in {}: {}",
+                        binding.from.display(),
+                        path.display(),
+                        e
+                    ));
+                }
+            }
+
+            // A directory or a glob binds every template it holds to the
+            // same path below the destination directory.
+            let pattern = match Pattern::new(&binding.from) {
+                Some(pattern) => pattern,
+                None => {
+                    bindings.push(binding);
+                    continue;
+                }
+            };
+            if binding.to.exists() && !binding.to.is_dir() {
+                return Err(format!(
+                    "Invalid binding {} in {}: {} is not a directory",
+                    binding.from.display(),
+                    path.display(),
+                    binding.to.display()
+                ));
+            }
+            let (files, _) = pattern.walk();
+            if files.is_empty() {
+                warn!("No template matches {}", binding.from.display());
+            }
+            for file in files {
+                bindings.push(PathBinding {
+                    to: pattern.destination(&file, &binding.to),
+                    from: file,
+                    delimiters: binding.delimiters.clone(),
+                    expanded: true
+                });
+            }
+            bindings_file.patterns.push(pattern);
         }
+        bindings_file.bindings = bindings;
 
-        let variables_file = match File::open(config_files.variables) {
-            Ok(f) => f,
-            Err(e) => return Err(format!(
-                "Couldn't open {}: {}",
-                config_files.variables.display(),
-                e.description()
-            ))
-        };
+        Ok(bindings_file)
+    }
 
-        let variables: HashMap<String, String>
-            = match serde_yaml::from_reader(variables_file) {
-            Ok(c) => c,
-            Err(e) => return Err(format!(
-                "Couldn't parse {}: {}",
-                config_files.variables.display(),
-                e.description()
-            ))
+    // Parses a layer of variables on top of the files it extends. `stack`
+    // holds the files being loaded, to catch a file extending itself.
+    fn load_variables(document: serde_yaml::Value, path: &Path,
+        stack: &mut Vec<PathBuf>, sources: &mut Vec<PathBuf>)
+        -> Result<HashMap<String, Value>, String>
+    {
+        sources.push(path.to_path_buf());
+        let mut layer = Config::expand(
+            Config::parse_variables(document, path)?);
+
+        let extended = match layer.remove(EXTENDS) {
+            None => Vec::new(),
+            Some(Value::String(file)) => vec![file],
+            Some(Value::List(files)) => files.into_iter()
+                .map(|file| match file {
+                    Value::String(file) => Ok(file),
+                    _ => Err(())
+                })
+                .collect::<Result<Vec<String>, ()>>()
+                .map_err(|_| format!(
+                    "Couldn't parse {}: {} must list file names",
+                    path.display(), EXTENDS))?,
+            Some(_) => return Err(format!(
+                "Couldn't parse {}: {} must be a file name or a list of them",
+                path.display(), EXTENDS))
         };
 
+        let mut variables = HashMap::new();
+        for file in extended {
+            let file = Config::resolve_path(Path::new(&file), path.parent());
+            let canonical = Config::canonical(&file);
+            if stack.contains(&canonical) {
+                return Err(format!("{} extends itself", file.display()));
+            }
+            stack.push(canonical);
+            let document = Config::read(&file)?;
+            let base = Config::load_variables(document, &file, stack,
+                sources)?;
+            stack.pop();
+            Config::merge(&mut variables, base);
+        }
 
-        Ok(Config {
-            bindings,
-            variables
-        })
+        Config::merge(&mut variables, layer);
+        Ok(variables)
     }
 
-    fn resolve_path(path: &Path, parent: Option<&Path>) -> PathBuf {
+    // Overrides the variables of `base` with those of `layer`. Nested maps
+    // are merged field by field, and a map of indices sets elements of a
+    // list. Fields merged over a scalar are kept next to it as dotted keys,
+    // so that `font` and `font.size` both keep their value. Anything else is
+    // replaced.
+    fn merge(base: &mut HashMap<String, Value>,
+        layer: HashMap<String, Value>)
+    {
+        fn merge_value(base: &mut Value, value: Value, key: &str,
+            detached: &mut Vec<(String, Value)>)
+        {
+            match (base, value) {
+                (&mut Value::Map(ref mut base), Value::Map(fields)) => {
+                    for (field, value) in fields {
+                        if let Some(base) = base.get_mut(&field) {
+                            let key = format!("{}.{}", key, field);
+                            merge_value(base, value, &key, detached);
+                        }
+                        else {
+                            base.insert(field, value);
+                        }
+                    }
+                },
+                (&mut Value::List(ref mut base), Value::Map(ref fields))
+                    if fields.keys().all(|key| key.parse::<usize>()
+                        .map(|i| i < base.len())
+                        .unwrap_or(false)) =>
+                {
+                    for (field, value) in fields {
+                        let index: usize = field.parse().unwrap();
+                        let key = format!("{}.{}", key, field);
+                        merge_value(&mut base[index], value.clone(), &key,
+                            detached);
+                    }
+                },
+                (base, Value::Map(fields)) if base.as_text().is_some()
+                    && *base != Value::Null =>
+                {
+                    detached.extend(fields.into_iter().map(|(field, value)|
+                        (format!("{}.{}", key, field), value)));
+                },
+                (base, value) => *base = value
+            }
+        }
+
+        let mut pending: Vec<(String, Value)> = layer.into_iter().collect();
+        while let Some((key, value)) = pending.pop() {
+            if let Some(variable) = base.get_mut(&key) {
+                merge_value(variable, value, &key, &mut pending);
+            }
+            else {
+                base.insert(key, value);
+            }
+        }
+    }
+
+    // Turns dotted keys into nested maps, so that `color.bg` and
+    // `color: {bg: ...}` are the same variable when layers are merged. Within
+    // a layer, dotted keys override the nested value.
+    fn expand(variables: HashMap<String, Value>) -> HashMap<String, Value> {
+        let (mut dotted, plain): (Vec<_>, Vec<_>) = variables.into_iter()
+            .map(|(key, value)| (key, Config::expand_value(value)))
+            .partition(|(key, _): &(String, Value)| key.contains('.')
+                && !key.split('.').any(str::is_empty));
+        let mut expanded: HashMap<String, Value> = plain.into_iter().collect();
+
+        dotted.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, value) in dotted {
+            let mut names: Vec<&str> = key.split('.').collect();
+            let mut value = value;
+            while names.len() > 1 {
+                let mut fields = BTreeMap::new();
+                fields.insert(names.pop().unwrap().to_string(), value);
+                value = Value::Map(fields);
+            }
+            let mut layer = HashMap::new();
+            layer.insert(names[0].to_string(), value);
+            Config::merge(&mut expanded, layer);
+        }
+        expanded
+    }
+
+    fn expand_value(value: Value) -> Value {
+        match value {
+            Value::Map(fields) => Value::Map(
+                Config::expand(fields.into_iter().collect())
+                    .into_iter()
+                    .collect()),
+            Value::List(list) => Value::List(list.into_iter()
+                .map(Config::expand_value)
+                .collect()),
+            value => value
+        }
+    }
+
+    fn canonical(path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    fn parse_variables(document: serde_yaml::Value, path: &Path)
+        -> Result<HashMap<String, Value>, String>
+    {
+        let variables = match Value::from_yaml(document, "") {
+            Ok(Value::Map(fields)) => Ok(fields.into_iter().collect()),
+            Ok(_) => Err("expected a map of variables".to_string()),
+            Err(e) => Err(e)
+        };
+        variables.map_err(|e| format!(
+            "Couldn't parse {}: {}",
+            path.display(),
+            e
+        ))
+    }
+
+    pub fn resolve_path(path: &Path, parent: Option<&Path>) -> PathBuf {
 
         if path.is_relative() {
 
@@ -102,3 +557,338 @@ impl Config {
         PathBuf::from(path)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempDir;
+    use generator::{Generator, Options};
+    use std::fs;
+
+    #[test]
+    fn test_combined() {
+        let dir = TempDir::new("combined");
+        let path = dir.join("config.yaml");
+        fs::write(&path, "
+            delimiters: {open: '<%', close: '%>'}
+            bindings:
+                - {from: a.cfy, to: a.conf}
+            variables:
+                font: Hack
+        ").unwrap();
+
+        let config = Config::new(&ConfigFiles::combined(&path)).unwrap();
+        assert_eq!(config.bindings.len(), 1);
+        assert_eq!(config.bindings[0].from, dir.join("a.cfy"));
+        assert_eq!(config.bindings[0].delimiters.as_ref().unwrap().open, "<%");
+        assert_eq!(config.variables["font"],
+            Value::String("Hack".to_string()));
+
+        let bindings = dir.join("bindings.yaml");
+        let variables = dir.join("variables.yaml");
+        fs::write(&bindings, "- {from: b.cfy, to: b.conf}").unwrap();
+        fs::write(&variables, "font: Fira").unwrap();
+        let config = Config::new(&ConfigFiles {
+            bindings: &bindings,
+            variables: vec![&variables],
+            profile: None
+        }).unwrap();
+        assert_eq!(config.bindings[0].to, dir.join("b.conf"));
+        assert_eq!(config.variables["font"],
+            Value::String("Fira".to_string()));
+    }
+
+    #[test]
+    fn test_environment() {
+        let dir = TempDir::new("environment");
+        let path = dir.join("config.yaml");
+        fs::write(&path, "
+            environment: [SHOWN, MINE, UNSET, ALIAS]
+            bindings: []
+            variables:
+                env: {MINE: mine}
+                HIDDEN: '@env.SHOWN'
+        ").unwrap();
+        let lookup = |name: &str| match name {
+            "SHOWN" => Some("${{HIDDEN}}".to_string()),
+            "MINE" => Some("theirs".to_string()),
+            "ALIAS" => Some("@HIDDEN".to_string()),
+            "HIDDEN" => Some("hidden".to_string()),
+            _ => None
+        };
+
+        let config = Config::load(&ConfigFiles::combined(&path), &lookup)
+            .unwrap();
+        let string = |s: &str| Value::String(s.to_string());
+        let env = &config.variables["env"];
+        assert_eq!(env.get("SHOWN"), Some(&string("${{HIDDEN}}")));
+        assert_eq!(env.get("MINE"), Some(&string("mine")));
+        assert_eq!(env.get("UNSET"), None);
+        assert_eq!(env.get("HIDDEN"), None);
+        assert_eq!(config.variables["HIDDEN"], string("@env.SHOWN"));
+        let mut literals: Vec<&str> = config.literals.iter()
+            .map(|key| &key[..])
+            .collect();
+        literals.sort();
+        assert_eq!(literals, vec!["env.ALIAS", "env.SHOWN"]);
+    }
+
+    #[test]
+    fn test_invalid_variables() {
+        let dir = TempDir::new("invalid");
+        let path = dir.join("config.yaml");
+        let error = |variables: &str| {
+            fs::write(&path, format!("bindings: []\nvariables:{}", variables))
+                .unwrap();
+            Config::new(&ConfigFiles::combined(&path)).unwrap_err()
+        };
+
+        assert_eq!(error("\n  color: {bright: [{[1, 2]: x}]}"), format!(
+            "Couldn't parse {}: invalid key in 'color.bright.0': expected a \
+            string, a number or a boolean, found a list", path.display()));
+        assert_eq!(error(" [a]"), format!(
+            "Couldn't parse {}: expected a map of variables", path.display()));
+        let syntax = error("\n  font: [Hack\n");
+        assert!(syntax.starts_with(&format!("Couldn't parse {}: ",
+            path.display())), "{}", syntax);
+        assert!(syntax.ends_with("at line 4 column 1"), "{}", syntax);
+    }
+
+    #[test]
+    fn test_patterns() {
+        let dir = TempDir::new("patterns");
+        fs::create_dir_all(dir.join("themes/bar")).unwrap();
+        fs::write(dir.join("themes/a.conf.cfy"), "").unwrap();
+        fs::write(dir.join("themes/bar/b.cfy"), "").unwrap();
+        let path = dir.join("config.yaml");
+        fs::write(&path, "
+            bindings:
+                - {from: themes/**/*.cfy, to: out}
+                - {from: themes/a.conf.cfy, to: a.conf}
+        ").unwrap();
+
+        let config = Config::new(&ConfigFiles::combined(&path)).unwrap();
+        let bindings: Vec<(PathBuf, PathBuf)> = config.bindings.iter()
+            .map(|b| (b.from.clone(), b.to.clone()))
+            .collect();
+        assert_eq!(bindings, vec![
+            (dir.join("themes/a.conf.cfy"), dir.join("out/a.conf")),
+            (dir.join("themes/bar/b.cfy"), dir.join("out/bar/b")),
+            (dir.join("themes/a.conf.cfy"), dir.join("a.conf"))
+        ]);
+        assert_eq!(config.patterns.len(), 1);
+        assert_eq!(config.patterns[0].base, dir.join("themes"));
+
+        fs::write(&path, "
+            bindings: [{from: themes, to: themes/a.conf.cfy}]
+        ").unwrap();
+        assert!(Config::new(&ConfigFiles::combined(&path)).is_err());
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = TempDir::new("layers");
+        fs::create_dir_all(dir.join("themes")).unwrap();
+        let path = dir.join("config.yaml");
+        fs::write(&path, "
+            bindings: []
+            variables:
+                extends: palette.yaml
+                color: {bg: '#000000'}
+        ").unwrap();
+        fs::write(dir.join("palette.yaml"), "
+            color: {bg: '#ffffff', fg: '#111111'}
+            font: Hack
+            accent: '@color.fg'
+        ").unwrap();
+        let machine = dir.join("machine.yaml");
+        fs::write(&machine, "
+            extends: [themes/nord.yaml]
+            font: Fira
+        ").unwrap();
+        let nord = dir.join("themes/nord.yaml");
+        fs::write(&nord, "{color: {fg: '#eceff4'}, font: Iosevka}").unwrap();
+
+        let config = Config::new(&ConfigFiles {
+            bindings: &path,
+            variables: vec![&path, &machine],
+            profile: None
+        }).unwrap();
+        let string = |s: &str| Value::String(s.to_string());
+        let color = config.variables["color"].clone();
+        assert_eq!(color.get("bg"), Some(&string("#000000")));
+        assert_eq!(color.get("fg"), Some(&string("#eceff4")));
+        assert_eq!(config.variables["font"], string("Fira"));
+        assert_eq!(config.variables["accent"], string("@color.fg"));
+        assert!(!config.variables.contains_key("extends"));
+        assert_eq!(config.sources, vec![path.clone(), dir.join("palette.yaml"),
+            machine.clone(), nord.clone()]);
+
+        fs::write(&nord, "extends: ../machine.yaml").unwrap();
+        let error = Config::new(&ConfigFiles {
+            bindings: &path,
+            variables: vec![&machine],
+            profile: None
+        }).unwrap_err();
+        assert!(error.ends_with("machine.yaml extends itself"), "{}", error);
+    }
+
+    #[test]
+    fn test_dotted_layers() {
+        let dir = TempDir::new("dotted");
+        let nested = dir.join("nested.yaml");
+        let dotted = dir.join("dotted.yaml");
+        fs::write(&nested, "
+            color: {bg: '#000000', fg: '#ffffff'}
+            fonts: [Hack, Noto]
+        ").unwrap();
+        fs::write(&dotted, "
+            color.bg: '#2e3440'
+            fonts.1: Fira
+            size.bar: 11
+        ").unwrap();
+        let load = |variables: Vec<&Path>| Config::new(&ConfigFiles {
+            bindings: &dir.join("bindings.yaml"),
+            variables,
+            profile: None
+        }).unwrap().variables;
+        fs::write(dir.join("bindings.yaml"), "[]").unwrap();
+        let string = |s: &str| Value::String(s.to_string());
+
+        let variables = load(vec![&nested, &dotted]);
+        assert_eq!(variables["color"].get("bg"), Some(&string("#2e3440")));
+        assert_eq!(variables["color"].get("fg"), Some(&string("#ffffff")));
+        assert_eq!(variables["fonts"],
+            Value::List(vec![string("Hack"), string("Fira")]));
+        assert_eq!(variables["size"].get("bar"), Some(&Value::Integer(11)));
+        assert!(!variables.contains_key("color.bg"));
+
+        let variables = load(vec![&dotted, &nested]);
+        assert_eq!(variables["color"].get("bg"), Some(&string("#000000")));
+        assert_eq!(variables["fonts"],
+            Value::List(vec![string("Hack"), string("Noto")]));
+        assert_eq!(variables["size"].get("bar"), Some(&Value::Integer(11)));
+
+        fs::write(&dotted, "{fonts: [Hack, Noto], fonts.1: Fira}").unwrap();
+        assert_eq!(load(vec![&dotted])["fonts"],
+            Value::List(vec![string("Hack"), string("Fira")]));
+
+        // A scalar keeps its value next to its dotted fields, in one layer
+        // or across several.
+        fs::write(&dotted, "{font: Hack, font.size: 11}").unwrap();
+        fs::write(&nested, "{font: {size: 12, style: bold}}").unwrap();
+        let variables = load(vec![&dotted]);
+        assert_eq!(variables["font"], string("Hack"));
+        assert_eq!(variables["font.size"], Value::Integer(11));
+        let variables = load(vec![&dotted, &nested]);
+        assert_eq!(variables["font"], string("Hack"));
+        assert_eq!(variables["font.size"], Value::Integer(12));
+        assert_eq!(variables["font.style"], string("bold"));
+        fs::write(dir.join("font.cfy"), "${{font}} ${{font.size}}").unwrap();
+        let binding = PathBinding {
+            from: dir.join("font.cfy"),
+            to: dir.join("font"),
+            delimiters: None,
+            expanded: false
+        };
+        let generator = Generator::new(&variables, &HashSet::new(),
+            Options { strict: true }).unwrap();
+        assert_eq!(generator.generate(&binding).unwrap().0, "Hack 12");
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dir = TempDir::new("profiles");
+        let path = dir.join("config.yaml");
+        let profile = dir.join("state/profile");
+        fs::write(&path, "
+            bindings: []
+            variables:
+                font: Hack
+                color: {bg: '#000000', fg: '#ffffff'}
+                profiles:
+                    gruvbox: {color: {bg: '#282828'}}
+                    nord: {color: {bg: '#2e3440'}, font: Iosevka}
+        ").unwrap();
+        let config_files = ConfigFiles {
+            bindings: &path,
+            variables: vec![&path],
+            profile: Some(&profile)
+        };
+        let string = |s: &str| Value::String(s.to_string());
+
+        let config = Config::new(&config_files).unwrap();
+        assert_eq!(config.variables["color"].get("bg"), Some(&string("#000000")));
+        assert!(!config.variables.contains_key("profiles"));
+        assert_eq!(config.sources, vec![path.clone(), profile.clone()]);
+
+        Config::set_profile(&config_files, "nord").unwrap();
+        assert_eq!(fs::read_to_string(&profile).unwrap(), "nord\n");
+        let config = Config::new(&config_files).unwrap();
+        assert_eq!(config.variables["color"].get("bg"), Some(&string("#2e3440")));
+        assert_eq!(config.variables["color"].get("fg"), Some(&string("#ffffff")));
+        assert_eq!(config.variables["font"], string("Iosevka"));
+
+        assert_eq!(Config::set_profile(&config_files, "dracula").unwrap_err(),
+            "Unknown profile 'dracula', expected one of: gruvbox, nord");
+        assert_eq!(fs::read_to_string(&profile).unwrap(), "nord\n");
+
+        // A later layer overrides the profile of an earlier one, and may
+        // define profiles of its own.
+        let machine = dir.join("machine.yaml");
+        fs::write(&machine, "
+            font: Fira
+            profiles: {dracula: {font: Hasklig}}
+        ").unwrap();
+        let layered = ConfigFiles {
+            bindings: &path,
+            variables: vec![&path, &machine],
+            profile: Some(&profile)
+        };
+        let config = Config::new(&layered).unwrap();
+        assert_eq!(config.variables["color"].get("bg"), Some(&string("#2e3440")));
+        assert_eq!(config.variables["font"], string("Fira"));
+        Config::set_profile(&layered, "dracula").unwrap();
+        let config = Config::new(&layered).unwrap();
+        assert_eq!(config.variables["font"], string("Hasklig"));
+
+        // An active profile which no longer exists is ignored.
+        let config = Config::new(&config_files).unwrap();
+        assert_eq!(config.variables["color"].get("bg"), Some(&string("#000000")));
+        assert_eq!(config.variables["font"], string("Hack"));
+    }
+
+    #[test]
+    fn test_no_profiles() {
+        let dir = TempDir::new("no-profiles");
+        let path = dir.join("config.yaml");
+        let profile = dir.join("state/profile");
+        fs::write(&path, "{bindings: [], variables: {font: Hack}}").unwrap();
+        let config_files = ConfigFiles {
+            bindings: &path,
+            variables: vec![&path],
+            profile: Some(&profile)
+        };
+
+        assert_eq!(Config::set_profile(&config_files, "typo").unwrap_err(),
+            "Unknown profile 'typo', the config defines no profiles");
+        assert!(!profile.exists());
+
+        // A profile left active once its config dropped profiles is ignored.
+        fs::create_dir_all(dir.join("state")).unwrap();
+        fs::write(&profile, "nord\n").unwrap();
+        let config = Config::new(&config_files).unwrap();
+        assert_eq!(config.variables["font"],
+            Value::String("Hack".to_string()));
+    }
+
+    #[test]
+    fn test_profile_file() {
+        let dir = TempDir::new("profile-file");
+        let a = Config::profile_file(&dir.join("a/config.yaml")).unwrap();
+        let b = Config::profile_file(&dir.join("b/config.yaml")).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a.parent(), b.parent());
+        assert!(a.parent().unwrap().ends_with("confy/profiles"));
+    }
+}
@@ -0,0 +1,320 @@
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize)
+}
+
+// Builds a unified diff between two texts with `context` lines around each
+// change, or returns `None` when they are identical.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str,
+    context: usize, color: bool) -> Option<String>
+{
+    if old == new {
+        return None;
+    }
+
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edits(&a, &b);
+    let paint = |style: &str, line: String| if color {
+        format!("{}{}{}", style, line.trim_end_matches('\n'), RESET)
+            + if line.ends_with('\n') { "\n" } else { "" }
+    }
+    else {
+        line
+    };
+
+    let mut output = String::new();
+    output.push_str(&paint(BOLD, format!("--- {}\n", old_name)));
+    output.push_str(&paint(BOLD, format!("+++ {}\n", new_name)));
+
+    // Positions in both texts before each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for edit in edits.iter() {
+        positions.push((i, j));
+        match *edit {
+            Edit::Equal(..) => { i += 1; j += 1; },
+            Edit::Delete(_) => i += 1,
+            Edit::Insert(_) => j += 1
+        }
+    }
+    positions.push((i, j));
+
+    for (start, end) in hunks(&edits, context) {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        output.push_str(&paint(CYAN, format!("@@ -{} +{} @@\n",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start))));
+
+        for edit in edits[start..end].iter() {
+            let (prefix, line, style) = match *edit {
+                Edit::Equal(i, _) => (' ', a[i], ""),
+                Edit::Delete(i) => ('-', a[i], RED),
+                Edit::Insert(j) => ('+', b[j], GREEN)
+            };
+            let mut line = format!("{}{}", prefix, line);
+            if !line.ends_with('\n') {
+                line.push_str("\n\\ No newline at end of file\n");
+            }
+            output.push_str(&if style.is_empty() {
+                line
+            }
+            else {
+                paint(style, line)
+            });
+        }
+    }
+
+    Some(output)
+}
+
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count)
+    }
+}
+
+// Groups changes closer than twice the context into ranges of edits.
+fn hunks(edits: &[Edit], context: usize) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+
+    for (i, edit) in edits.iter().enumerate() {
+        if let Edit::Equal(..) = *edit {
+            continue;
+        }
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end))
+        }
+    }
+
+    hunks
+}
+
+// Shortest edit script between two sequences of lines, after Myers, in
+// linear space: the middle snake of the edit graph splits the problem in two
+// until no edit is left.
+fn edits(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    compare(a, b, 0, 0, &mut edits);
+    edits
+}
+
+// Appends the edits turning `a` into `b`, found at lines `x` and `y` of the
+// whole texts.
+fn compare(a: &[&str], b: &[&str], x: usize, y: usize,
+    edits: &mut Vec<Edit>)
+{
+    let prefix = a.iter().zip(b).take_while(|&(p, q)| p == q).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|&(p, q)| p == q)
+        .count();
+    let (a_end, b_end) = (a.len() - suffix, b.len() - suffix);
+    let (middle_a, middle_b) = (&a[prefix..a_end], &b[prefix..b_end]);
+    let (middle_x, middle_y) = (x + prefix, y + prefix);
+
+    edits.extend((0..prefix).map(|i| Edit::Equal(x + i, y + i)));
+    match bisect(middle_a, middle_b) {
+        Some((split_x, split_y)) => {
+            compare(&middle_a[..split_x], &middle_b[..split_y],
+                middle_x, middle_y, edits);
+            compare(&middle_a[split_x..], &middle_b[split_y..],
+                middle_x + split_x, middle_y + split_y, edits);
+        },
+        None => {
+            edits.extend((0..middle_a.len())
+                .map(|i| Edit::Delete(middle_x + i)));
+            edits.extend((0..middle_b.len())
+                .map(|i| Edit::Insert(middle_y + i)));
+        }
+    };
+    edits.extend((0..suffix)
+        .map(|i| Edit::Equal(x + a_end + i, y + b_end + i)));
+}
+
+// Where the forward and backward searches for the shortest edit script
+// meet, or `None` when `a` and `b` have nothing in common. Either side being
+// empty is answered right away.
+fn bisect(a: &[&str], b: &[&str]) -> Option<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    if n == 0 || m == 0 {
+        return None;
+    }
+    let max = (n + m + 1) / 2;
+    let offset = max;
+    let length = 2 * max + 2;
+    // The furthest `x` reached on every diagonal, from the start and from
+    // the end, or -1 when not reached yet.
+    let mut forward = vec![-1isize; length as usize];
+    let mut backward = vec![-1isize; length as usize];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+    let delta = n - m;
+    // With an odd delta the paths meet while extending the forward one.
+    let odd = delta % 2 != 0;
+    // Diagonals which left the edit graph are skipped from then on.
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+
+    for d in 0..max {
+        let mut k = -d + forward_start;
+        while k <= d - forward_end {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d
+                || (k != d && forward[i - 1] < forward[i + 1])
+            {
+                forward[i + 1]
+            }
+            else {
+                forward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+            if x > n {
+                forward_end += 2;
+            }
+            else if y > m {
+                forward_start += 2;
+            }
+            else if odd {
+                let j = offset + delta - k;
+                if j >= 0 && j < length && backward[j as usize] != -1
+                    && x >= n - backward[j as usize]
+                {
+                    return Some((x as usize, y as usize));
+                }
+            }
+            k += 2;
+        }
+
+        let mut k = -d + backward_start;
+        while k <= d - backward_end {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d
+                || (k != d && backward[i - 1] < backward[i + 1])
+            {
+                backward[i + 1]
+            }
+            else {
+                backward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m
+                && a[(n - x - 1) as usize] == b[(m - y - 1) as usize]
+            {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+            if x > n {
+                backward_end += 2;
+            }
+            else if y > m {
+                backward_start += 2;
+            }
+            else if !odd {
+                let j = offset + delta - k;
+                if j >= 0 && j < length && forward[j as usize] != -1 {
+                    let forward_x = forward[j as usize];
+                    let forward_y = forward_x - (delta - k);
+                    if forward_x >= n - x {
+                        return Some((forward_x as usize, forward_y as usize));
+                    }
+                }
+            }
+            k += 2;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_edits() {
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        let edits = edits(&a, &b);
+
+        let deleted = edits.iter()
+            .filter(|e| matches!(**e, Edit::Delete(_)))
+            .count();
+        let inserted = edits.iter()
+            .filter(|e| matches!(**e, Edit::Insert(_)))
+            .count();
+        assert_eq!((deleted, inserted), (3, 2));
+
+        assert_eq!(super::edits(&[], &["a"]), vec![Edit::Insert(0)]);
+        assert_eq!(super::edits(&["a"], &[]), vec![Edit::Delete(0)]);
+    }
+
+    #[test]
+    fn test_large() {
+        let lines: Vec<String> = (0..20000).map(|i| format!("{}", i % 97))
+            .collect();
+        let a: Vec<&str> = lines.iter().map(|l| &l[..]).collect();
+        let mut b: Vec<&str> = a.iter()
+            .map(|l| if l.len() == 1 { "x" } else { l })
+            .collect();
+        b.insert(5000, "new");
+
+        assert_eq!(edits(&[], &a).len(), a.len());
+        assert_eq!(edits(&a, &[]).len(), a.len());
+
+        let edits = edits(&a, &b);
+        let deleted = edits.iter()
+            .filter(|e| matches!(**e, Edit::Delete(_)))
+            .count();
+        let inserted = edits.iter()
+            .filter(|e| matches!(**e, Edit::Insert(_)))
+            .count();
+        let changed = a.iter().filter(|l| l.len() == 1).count();
+        assert_eq!((deleted, inserted), (changed, changed + 1));
+        assert!(unified(&lines.join("\n"), "", "a", "b", 3, false).is_some());
+    }
+
+    #[test]
+    fn test_unified() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9";
+
+        assert_eq!(unified(old, old, "a", "b", 1, false), None);
+        assert_eq!(unified(old, new, "a", "b", 1, false).unwrap(), "\
+            --- a\n\
+            +++ b\n\
+            @@ -2,3 +2,3 @@\n \
+            2\n\
+            -3\n\
+            +three\n \
+            4\n\
+            @@ -8,2 +8,2 @@\n \
+            8\n\
+            -9\n\
+            +9\n\
+            \\ No newline at end of file\n");
+        assert_eq!(unified("", "a\n", "a", "b", 3, false).unwrap(),
+            "--- a\n+++ b\n@@ -0,0 +1 @@\n+a\n");
+    }
+}
@@ -0,0 +1,744 @@
+use std::fmt;
+
+use variables::Value;
+use template::Operand;
+use filters;
+
+
+#[derive(Debug, PartialEq)]
+pub struct Expression {
+    pub alternatives: Vec<Operand>,
+    pub filters: Vec<Filter>
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub arguments: Vec<Operand>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Arithmetic {
+    Operand(Operand),
+    Negate(Box<Arithmetic>),
+    Binary(Operator, Box<Arithmetic>, Box<Arithmetic>),
+    Call(Function, Vec<Arithmetic>)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Min,
+    Max,
+    Round,
+    Clamp
+}
+
+// A parse error, `offset` counting characters from the start of the
+// expression.
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub offset: usize,
+    pub message: String
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Literal(String),
+    Operator(Operator),
+    Pipe,
+    Default,
+    Comma,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket
+}
+
+// Holds the remaining tokens, with their offsets, in reverse order.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    end: usize
+}
+
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Expression, Error> {
+        let mut tokens = Parser::tokenize(input)?;
+        tokens.reverse();
+        let mut parser = Parser { tokens, end: input.chars().count() };
+
+        if parser.peek().is_none() {
+            return Err(parser.error("empty expression".to_string()));
+        }
+        let mut alternatives = vec![parser.operand()?];
+
+        let mut filters = Vec::new();
+        if parser.peek() == Some(&Token::OpenBracket) {
+            parser.next();
+            filters.push(parser.slice()?);
+        }
+
+        while parser.peek() == Some(&Token::Default) {
+            parser.next();
+            if parser.peek().is_none() {
+                return Err(parser.error(
+                    "expected a fallback after '??'".to_string()));
+            }
+            alternatives.push(parser.operand()?);
+        }
+
+        loop {
+            let offset = parser.offset();
+            match parser.next() {
+                Some(Token::Pipe) => filters.push(parser.filter()?),
+                Some(token) => return Err(Error {
+                    offset,
+                    message: format!("expected '|', found {}", token)
+                }),
+                None => break
+            };
+        }
+
+        Ok(Expression { alternatives, filters })
+    }
+
+    // Parses a lone key, literal or calculation, as found in conditions.
+    pub fn parse_operand(input: &str) -> Result<Operand, Error> {
+        let mut tokens = Parser::tokenize(input)?;
+        tokens.reverse();
+        let mut parser = Parser { tokens, end: input.chars().count() };
+
+        let operand = parser.operand()?;
+        let offset = parser.offset();
+        match parser.next() {
+            None => Ok(operand),
+            Some(token) => Err(Error {
+                offset,
+                message: format!("unexpected {}", token)
+            })
+        }
+    }
+}
+
+impl Arithmetic {
+    // Every variable the calculation depends on, in order.
+    pub fn variables(&self) -> Vec<&str> {
+        match *self {
+            Arithmetic::Operand(Operand::Variable(ref key)) => vec![key],
+            Arithmetic::Operand(_) => Vec::new(),
+            Arithmetic::Negate(ref a) => a.variables(),
+            Arithmetic::Binary(_, ref a, ref b) => {
+                let mut variables = a.variables();
+                variables.extend(b.variables());
+                variables
+            },
+            Arithmetic::Call(_, ref arguments) => arguments.iter()
+                .flat_map(|a| a.variables())
+                .collect()
+        }
+    }
+}
+
+impl Operator {
+    pub fn apply(self, a: f64, b: f64) -> Result<f64, String> {
+        match self {
+            Operator::Add => Ok(a + b),
+            Operator::Subtract => Ok(a - b),
+            Operator::Multiply => Ok(a * b),
+            Operator::Divide | Operator::Remainder if b == 0.0 =>
+                Err("division by zero".to_string()),
+            Operator::Divide => Ok(a / b),
+            Operator::Remainder => Ok(a % b)
+        }
+    }
+}
+
+impl Function {
+    fn lookup(name: &str) -> Option<Function> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "round" => Some(Function::Round),
+            "clamp" => Some(Function::Clamp),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Round => "round",
+            Function::Clamp => "clamp"
+        }
+    }
+
+    // `round` takes an optional number of decimals.
+    pub fn apply(self, arguments: &[f64]) -> Result<f64, String> {
+        let (min, max) = match self {
+            Function::Min | Function::Max => (1, usize::MAX),
+            Function::Round => (1, 2),
+            Function::Clamp => (3, 3)
+        };
+        if arguments.len() < min || arguments.len() > max {
+            return Err(format!("wrong number of arguments to {}, got {}",
+                self.name(), arguments.len()));
+        }
+
+        Ok(match self {
+            Function::Min => arguments.iter().cloned().fold(f64::MAX, f64::min),
+            Function::Max => arguments.iter().cloned().fold(f64::MIN, f64::max),
+            Function::Round => {
+                let scale = 10f64.powi(
+                    arguments.get(1).cloned().unwrap_or(0.0) as i32);
+                (arguments[0] * scale).round() / scale
+            },
+            Function::Clamp => {
+                if arguments[1] > arguments[2] {
+                    return Err(format!("clamp bounds {} and {} are reversed",
+                        arguments[1], arguments[2]));
+                }
+                arguments[0].clamp(arguments[1], arguments[2])
+            }
+        })
+    }
+}
+
+impl Parser {
+    // `-` and `%` are operators only when standing alone, so that they may
+    // still appear in keys and percentages.
+    fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, Error> {
+        let mut tokens = Vec::new();
+        let mut chars = input.chars().enumerate().peekable();
+
+        while let Some((offset, c)) = chars.next() {
+            let token = match c {
+                '|' => Token::Pipe,
+                '?' => match chars.next() {
+                    Some((_, '?')) => Token::Default,
+                    _ => return Err(Error {
+                        offset,
+                        message: "expected '??'".to_string()
+                    })
+                },
+                ',' => Token::Comma,
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '[' => Token::OpenBracket,
+                ']' => Token::CloseBracket,
+                '"' | '\'' => {
+                    let mut literal = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, escaped)) => literal.push(escaped),
+                                None => break
+                            },
+                            Some((_, q)) if q == c => break,
+                            Some((_, other)) => literal.push(other),
+                            None => return Err(Error {
+                                offset,
+                                message: format!(
+                                    "unterminated literal {}{}", c, literal)
+                            })
+                        }
+                    }
+                    Token::Literal(literal)
+                },
+                c if c.is_whitespace() => continue,
+                c => {
+                    let mut word = c.to_string();
+                    while let Some(&(_, next)) = chars.peek() {
+                        if next.is_whitespace()
+                            || "|?,()[]\"'".contains(next)
+                        {
+                            break;
+                        }
+                        word.push(next);
+                        chars.next();
+                    }
+                    match Parser::operator(&word) {
+                        Some(operator) => Token::Operator(operator),
+                        None => Token::Word(word)
+                    }
+                }
+            };
+            tokens.push((token, offset));
+        }
+
+        Ok(Parser::split_operators(tokens))
+    }
+
+    fn operator(word: &str) -> Option<Operator> {
+        match word {
+            "+" => Some(Operator::Add),
+            "-" => Some(Operator::Subtract),
+            "*" => Some(Operator::Multiply),
+            "/" => Some(Operator::Divide),
+            "%" => Some(Operator::Remainder),
+            _ => None
+        }
+    }
+
+    // Keys may hold `+`, `*` and `/`, as in `path/to`, so these are only
+    // operators on their own, or anywhere in a calculation: an expression
+    // with an operator on its own or a call to a function. A number right
+    // after an operand, as in `a -1`, is subtracted.
+    fn split_operators(tokens: Vec<(Token, usize)>) -> Vec<(Token, usize)> {
+        let mut split: Vec<(Token, usize)> = Vec::with_capacity(tokens.len());
+        for (token, offset) in tokens {
+            let follows_operand = matches!(split.last(),
+                Some((Token::Word(_), _)) | Some((Token::Literal(_), _))
+                    | Some((Token::Close, _))
+                    | Some((Token::CloseBracket, _)));
+            match token {
+                Token::Word(ref word) if follows_operand
+                    && word.starts_with('-')
+                    && word[1..].starts_with(|c: char| c.is_ascii_digit()) =>
+                {
+                    split.push((Token::Operator(Operator::Subtract), offset));
+                    split.push((Token::Word(word[1..].to_string()),
+                        offset + 1));
+                },
+                token => split.push((token, offset))
+            }
+        }
+
+        let arithmetic = split.iter().enumerate().any(|(i, (token, _))|
+            match *token {
+                Token::Operator(_) => true,
+                Token::Word(ref word) => Function::lookup(word).is_some()
+                    && split.get(i + 1).map(|t| &t.0) == Some(&Token::Open)
+                    && (i == 0 || split[i - 1].0 != Token::Pipe),
+                _ => false
+            });
+        if !arithmetic {
+            return split;
+        }
+
+        let mut tokens = Vec::with_capacity(split.len());
+        for (token, offset) in split {
+            let word = match token {
+                Token::Word(word) => word,
+                token => {
+                    tokens.push((token, offset));
+                    continue;
+                }
+            };
+            let mut start = None;
+            for (i, c) in word.chars().enumerate() {
+                if !"+*/".contains(c) {
+                    start = start.or(Some(i));
+                    continue;
+                }
+                if let Some(j) = start.take() {
+                    let piece: String = word.chars().skip(j).take(i - j)
+                        .collect();
+                    tokens.push((Token::Word(piece), offset + j));
+                }
+                let operator = Parser::operator(&c.to_string()).unwrap();
+                tokens.push((Token::Operator(operator), offset + i));
+            }
+            if let Some(j) = start {
+                tokens.push((Token::Word(word.chars().skip(j).collect()),
+                    offset + j));
+            }
+        }
+        tokens
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.pop().map(|(token, _)| token)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.last().map(|(token, _)| token)
+    }
+
+    // Offset of the next token, or of the end of the input.
+    fn offset(&self) -> usize {
+        self.tokens.last().map_or(self.end, |&(_, offset)| offset)
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error { offset: self.offset(), message }
+    }
+
+    // A key, a literal, or a calculation.
+    fn operand(&mut self) -> Result<Operand, Error> {
+        match self.sum()? {
+            Arithmetic::Operand(operand) => Ok(operand),
+            arithmetic => Ok(Operand::Arithmetic(Box::new(arithmetic)))
+        }
+    }
+
+    fn sum(&mut self) -> Result<Arithmetic, Error> {
+        let mut left = self.product()?;
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Operator(o))
+                    if o == Operator::Add || o == Operator::Subtract => o,
+                _ => return Ok(left)
+            };
+            self.next();
+            let right = self.product()?;
+            left = Arithmetic::Binary(
+                operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn product(&mut self) -> Result<Arithmetic, Error> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Operator(o))
+                    if o != Operator::Add && o != Operator::Subtract => o,
+                _ => return Ok(left)
+            };
+            self.next();
+            let right = self.unary()?;
+            left = Arithmetic::Binary(
+                operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Arithmetic, Error> {
+        if self.peek() == Some(&Token::Operator(Operator::Subtract)) {
+            self.next();
+            return Ok(Arithmetic::Negate(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Arithmetic, Error> {
+        let offset = self.offset();
+        let error = |message: String| Err(Error { offset, message });
+
+        match self.next() {
+            Some(Token::Word(w)) => {
+                if self.peek() != Some(&Token::Open) {
+                    return Ok(Arithmetic::Operand(Parser::word(w)));
+                }
+                let function = match Function::lookup(&w) {
+                    Some(f) => f,
+                    None => return error(format!("unknown function '{}'", w))
+                };
+                self.next();
+                let mut arguments = Vec::new();
+                loop {
+                    arguments.push(self.sum()?);
+                    match self.next() {
+                        Some(Token::Comma) => (),
+                        Some(Token::Close) => break,
+                        _ => return error(format!(
+                            "unterminated arguments to '{}'", w))
+                    };
+                }
+                Ok(Arithmetic::Call(function, arguments))
+            },
+            Some(Token::Literal(l)) =>
+                Ok(Arithmetic::Operand(Operand::Literal(Value::String(l)))),
+            Some(Token::Open) => {
+                let inner = self.sum()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => error("unclosed parenthesis".to_string())
+                }
+            },
+            Some(t) => error(format!("expected a value, found {}", t)),
+            None => error("expected a value".to_string())
+        }
+    }
+
+    // `key[start..end:step]` is shorthand for `key | slice(start, end, step)`,
+    // where each part may be omitted.
+    fn slice(&mut self) -> Result<Filter, Error> {
+        let offset = self.offset();
+        let error = |message: String| Err(Error { offset, message });
+
+        let range = match self.next() {
+            Some(Token::Word(w)) => w,
+            Some(t) => return error(format!("expected a range, found {}", t)),
+            None => return error("unterminated range".to_string())
+        };
+        match self.next() {
+            Some(Token::CloseBracket) => (),
+            _ => return error(format!("unterminated range [{}", range))
+        };
+
+        let dot = match range.find("..") {
+            Some(d) => d,
+            None => return error(format!("malformed range [{}]", range))
+        };
+        let (start, rest) = (&range[..dot], &range[dot + 2..]);
+        let (end, step) = match rest.find(':') {
+            Some(colon) => (&rest[..colon], Some(&rest[colon + 1..])),
+            None => (rest, None)
+        };
+        let arguments = Some(start).into_iter()
+            .chain(Some(end))
+            .chain(step)
+            .map(|part| Operand::Literal(Value::String(part.to_string())))
+            .collect();
+
+        Ok(Filter { name: "slice".to_string(), arguments })
+    }
+
+    fn filter(&mut self) -> Result<Filter, Error> {
+        let offset = self.offset();
+        let error = |message: String| Err(Error { offset, message });
+
+        let name = match self.next() {
+            Some(Token::Word(w)) => w,
+            Some(t) => return error(
+                format!("expected a filter name, found {}", t)),
+            None => return error("expected a filter name after '|'".to_string())
+        };
+        if filters::lookup(&name).is_none() {
+            return error(format!("unknown filter '{}'", name));
+        }
+
+        let mut arguments = Vec::new();
+        if self.peek() == Some(&Token::Open) {
+            self.next();
+            if self.peek() == Some(&Token::Close) {
+                self.next();
+                return Ok(Filter { name, arguments });
+            }
+            loop {
+                arguments.push(self.operand()?);
+                match self.next() {
+                    Some(Token::Comma) => (),
+                    Some(Token::Close) => break,
+                    _ => return error(format!(
+                        "unterminated arguments to '{}'", name))
+                };
+            }
+        }
+
+        Ok(Filter { name, arguments })
+    }
+
+    // Bare numbers, percentages, booleans and `null` are literals, any other
+    // bare word names a variable.
+    fn word(word: String) -> Operand {
+        if let Some(literal) = Value::parse_literal(&word) {
+            return Operand::Literal(literal);
+        }
+        match word.strip_suffix('%').map(str::parse::<f64>) {
+            Some(Ok(_)) => Operand::Literal(Value::String(word)),
+            _ => Operand::Variable(word)
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Word(ref w) => write!(f, "'{}'", w),
+            Token::Literal(ref l) => write!(f, "\"{}\"", l),
+            Token::Operator(o) => write!(f, "'{}'", o),
+            Token::Pipe => write!(f, "'|'"),
+            Token::Default => write!(f, "'??'"),
+            Token::Comma => write!(f, "','"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::OpenBracket => write!(f, "'['"),
+            Token::CloseBracket => write!(f, "']'")
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Remainder => "%"
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn literal(s: &str) -> Operand {
+        Operand::Literal(Value::String(s.to_string()))
+    }
+
+    fn variable(s: &str) -> Arithmetic {
+        Arithmetic::Operand(Operand::Variable(s.to_string()))
+    }
+
+    fn integer(i: i64) -> Arithmetic {
+        Arithmetic::Operand(Operand::Literal(Value::Integer(i)))
+    }
+
+    fn binary(operator: Operator, a: Arithmetic, b: Arithmetic) -> Arithmetic {
+        Arithmetic::Binary(operator, Box::new(a), Box::new(b))
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let expression = Expression::parse(
+            "color.0[1..] | replace('a', \"|\") | pad_left(8, fill) | upper")
+            .unwrap();
+
+        assert_eq!(expression, Expression {
+            alternatives: vec![Operand::Variable("color.0".to_string())],
+            filters: vec![
+                Filter {
+                    name: "slice".to_string(),
+                    arguments: vec![literal("1"), literal("")]
+                },
+                Filter {
+                    name: "replace".to_string(),
+                    arguments: vec![literal("a"), literal("|")]
+                },
+                Filter {
+                    name: "pad_left".to_string(),
+                    arguments: vec![
+                        Operand::Literal(Value::Integer(8)),
+                        Operand::Variable("fill".to_string())
+                    ]
+                },
+                Filter {
+                    name: "upper".to_string(),
+                    arguments: vec![]
+                }
+            ]
+        });
+
+        let darken = Expression::parse("c | darken(10%)").unwrap();
+        assert_eq!(darken.filters[0].arguments, vec![literal("10%")]);
+    }
+
+    #[test]
+    fn test_slices() {
+        let slice = |input: &str| Expression::parse(input).unwrap()
+            .filters.remove(0).arguments;
+
+        assert_eq!(slice("a[..]"), vec![literal(""), literal("")]);
+        assert_eq!(slice("a[-3..-1]"), vec![literal("-3"), literal("-1")]);
+        assert_eq!(slice("a[1..:2]"),
+            vec![literal("1"), literal(""), literal("2")]);
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let expression = Expression::parse(
+            "font.size??font.default ?? 11 ?? 'none' | suffix('pt')").unwrap();
+
+        assert_eq!(expression, Expression {
+            alternatives: vec![
+                Operand::Variable("font.size".to_string()),
+                Operand::Variable("font.default".to_string()),
+                Operand::Literal(Value::Integer(11)),
+                literal("none")
+            ],
+            filters: vec![
+                Filter {
+                    name: "suffix".to_string(),
+                    arguments: vec![literal("pt")]
+                }
+            ]
+        });
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let arithmetic = |input: &str| {
+            match Expression::parse(input).unwrap().alternatives.remove(0) {
+                Operand::Arithmetic(a) => *a,
+                other => panic!("unexpected {:?}", other)
+            }
+        };
+
+        assert_eq!(arithmetic("gap*2 + 1"), binary(Operator::Add,
+            binary(Operator::Multiply, variable("gap"), integer(2)),
+            integer(1)));
+        assert_eq!(arithmetic("(a - b) % 3"), binary(Operator::Remainder,
+            binary(Operator::Subtract, variable("a"), variable("b")),
+            integer(3)));
+        assert_eq!(arithmetic("- font-size / 2"), binary(Operator::Divide,
+            Arithmetic::Negate(Box::new(variable("font-size"))),
+            integer(2)));
+        assert_eq!(arithmetic("clamp(round(a), 0, max(b, 1))"),
+            Arithmetic::Call(Function::Clamp, vec![
+                Arithmetic::Call(Function::Round, vec![variable("a")]),
+                integer(0),
+                Arithmetic::Call(Function::Max, vec![variable("b"), integer(1)])
+            ]));
+        assert_eq!(arithmetic("a * b + a").variables(), vec!["a", "b", "a"]);
+        assert_eq!(arithmetic("a -1"), binary(Operator::Subtract,
+            variable("a"), integer(1)));
+        assert_eq!(arithmetic("max(a/2, b)"), Arithmetic::Call(Function::Max,
+            vec![binary(Operator::Divide, variable("a"), integer(2)),
+                variable("b")]));
+
+        // Outside of calculations, these are parts of keys.
+        let key = |input: &str| Expression::parse(input).unwrap()
+            .alternatives.remove(0);
+        assert_eq!(key("path/to"), Operand::Variable("path/to".to_string()));
+        assert_eq!(key("a+b | pad(4)"), Operand::Variable("a+b".to_string()));
+        assert_eq!(key("*.x ?? y"), Operand::Variable("*.x".to_string()));
+        assert_eq!(key("-1"), Operand::Literal(Value::Integer(-1)));
+
+        let expression = Expression::parse("a + 1 ?? b * 2 | pad(4)").unwrap();
+        assert_eq!(expression.alternatives.len(), 2);
+        assert_eq!(expression.filters.len(), 1);
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(Function::Min.apply(&[3.0, 1.0, 2.0]), Ok(1.0));
+        assert_eq!(Function::Max.apply(&[3.0, 1.0]), Ok(3.0));
+        assert_eq!(Function::Round.apply(&[2.5]), Ok(3.0));
+        assert_eq!(Function::Round.apply(&[0.1234, 2.0]), Ok(0.12));
+        assert_eq!(Function::Clamp.apply(&[300.0, 0.0, 255.0]), Ok(255.0));
+        assert!(Function::Clamp.apply(&[1.0, 2.0]).is_err());
+        assert!(Function::Clamp.apply(&[1.0, 2.0, 0.0]).is_err());
+        assert!(Function::Min.apply(&[]).is_err());
+        assert!(Operator::Divide.apply(1.0, 0.0).is_err());
+        assert_eq!(Operator::Remainder.apply(7.0, 4.0), Ok(3.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        let offset = |input: &str| Expression::parse(input).unwrap_err().offset;
+
+        assert!(Expression::parse("").is_err());
+        assert!(Expression::parse("a b").is_err());
+        assert!(Expression::parse("a |").is_err());
+        assert!(Expression::parse("a | nope").is_err());
+        assert!(Expression::parse("a | pad(1").is_err());
+        assert!(Expression::parse("a | pad(1 2)").is_err());
+        assert!(Expression::parse("a[1]").is_err());
+        assert!(Expression::parse("a | replace('x").is_err());
+        assert!(Expression::parse("a ? b").is_err());
+        assert!(Expression::parse("a ??").is_err());
+        assert!(Expression::parse("(a + 1").is_err());
+        assert!(Expression::parse("a + ").is_err());
+        assert!(Expression::parse("sqrt(a)").is_err());
+
+        assert_eq!(offset("a | nope"), 4);
+        assert_eq!(offset("a b"), 2);
+        assert_eq!(offset("gap * * 2"), 6);
+        assert_eq!(offset("a + "), 4);
+        assert_eq!(offset("(a + 1"), 0);
+        assert_eq!(offset("é + sqrt(1)"), 4);
+    }
+}
@@ -0,0 +1,555 @@
+use std::borrow::Cow;
+
+use variables::Value;
+use color::Color;
+
+
+pub type Function = fn(&Value, &[Value]) -> Result<Value, String>;
+
+
+pub fn lookup(name: &str) -> Option<Function> {
+    let function: Function = match name {
+        "upper" => upper,
+        "lower" => lower,
+        "trim" => trim,
+        "trim_hash" => trim_hash,
+        "replace" => replace,
+        "prefix" => prefix,
+        "suffix" => suffix,
+        "pad" => pad,
+        "pad_left" => pad_left,
+        "quote" => quote,
+        "json_escape" => json_escape,
+        "shell_escape" => shell_escape,
+        "base64" => base64,
+        "url_encode" => url_encode,
+        "slice" => slice,
+        "slice_bytes" => slice_bytes,
+        "join" => join,
+        "indent" => indent,
+        "hex" => hex,
+        "hexa" => hexa,
+        "argb" => argb,
+        "rgb" => rgb,
+        "rgba" => rgba,
+        "hsl" => hsl,
+        "rgb_float" => rgb_float,
+        "lighten" => lighten,
+        "darken" => darken,
+        "saturate" => saturate,
+        "desaturate" => desaturate,
+        "alpha" => alpha,
+        "invert" => invert,
+        "mix" => mix,
+        "contrast" => contrast,
+        _ => return None
+    };
+    Some(function)
+}
+
+fn upper(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(string(value)?.to_uppercase()))
+}
+
+fn lower(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(string(value)?.to_lowercase()))
+}
+
+// Strips whitespace, or any of the given characters, from both ends.
+fn trim(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 1)?;
+    let value = string(value)?;
+    let trimmed = match arguments.first() {
+        Some(chars) => {
+            let chars = string(chars)?;
+            value.trim_matches(|c| chars.contains(c))
+        },
+        None => value.trim()
+    };
+    Ok(Value::String(trimmed.to_string()))
+}
+
+fn trim_hash(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(string(value)?.trim_start_matches('#').to_string()))
+}
+
+fn replace(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 2, 2)?;
+    let (from, to) = (string(&arguments[0])?, string(&arguments[1])?);
+    if from.is_empty() {
+        return Err("cannot replace an empty string".to_string());
+    }
+    Ok(Value::String(string(value)?.replace(&*from, &to)))
+}
+
+fn prefix(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    Ok(Value::String(format!("{}{}", string(&arguments[0])?, string(value)?)))
+}
+
+fn suffix(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    Ok(Value::String(format!("{}{}", string(value)?, string(&arguments[0])?)))
+}
+
+// Pads on the right up to the given width in characters, with spaces or
+// the given fill character.
+fn pad(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    let (value, padding) = padding(value, arguments)?;
+    Ok(Value::String(format!("{}{}", value, padding)))
+}
+
+fn pad_left(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    let (value, padding) = padding(value, arguments)?;
+    Ok(Value::String(format!("{}{}", padding, value)))
+}
+
+fn padding<'a>(value: &'a Value, arguments: &[Value])
+    -> Result<(Cow<'a, str>, String), String>
+{
+    arity(arguments, 1, 2)?;
+    let value = string(value)?;
+    let width = unsigned(&arguments[0])?;
+    let fill = match arguments.get(1) {
+        Some(fill) => {
+            let fill = string(fill)?;
+            let mut chars = fill.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(format!(
+                    "fill must be a single character, got '{}'", fill))
+            }
+        },
+        None => ' '
+    };
+    let missing = width.saturating_sub(value.chars().count());
+    Ok((value, (0..missing).map(|_| fill).collect()))
+}
+
+fn quote(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    let value = string(value)?;
+    Ok(Value::String(format!(
+        "\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))))
+}
+
+fn json_escape(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    let mut escaped = String::new();
+    for c in string(value)?.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 =>
+                escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    Ok(Value::String(escaped))
+}
+
+// Single-quotes the value for POSIX shells.
+fn shell_escape(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(format!(
+        "'{}'", string(value)?.replace('\'', "'\\''"))))
+}
+
+fn base64(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    const ALPHABET: &[u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    arity(arguments, 0, 0)?;
+    let mut encoded = String::new();
+    for chunk in string(value)?.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0)
+        ];
+        let n = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8
+            | bytes[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[n >> (18 - 6 * i) & 0x3f] as char);
+            }
+            else {
+                encoded.push('=');
+            }
+        }
+    }
+    Ok(Value::String(encoded))
+}
+
+// Percent-encodes everything but the unreserved characters of RFC 3986.
+fn url_encode(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    let mut encoded = String::new();
+    for byte in string(value)?.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9'
+                | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    Ok(Value::String(encoded))
+}
+
+// Keeps the characters between two indices, counted from the end when
+// negative, and optionally only every `step` of them. An empty index stands
+// for the corresponding end of the value.
+fn slice(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 3)?;
+    let chars: Vec<char> = string(value)?.chars().collect();
+    let (start, end) = bounds(chars.len(), arguments)?;
+    let step = match arguments.get(2) {
+        Some(step) => match string(step)?.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!(
+                "step must be a positive integer, got '{}'", string(step)?))
+        },
+        None => 1
+    };
+    Ok(Value::String(chars[start..end].iter().step_by(step).collect()))
+}
+
+// Same as `slice`, counting bytes instead of characters.
+fn slice_bytes(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 2)?;
+    let value = string(value)?;
+    let (start, end) = bounds(value.len(), arguments)?;
+    match value.get(start..end) {
+        Some(slice) => Ok(Value::String(slice.to_string())),
+        None => Err(format!(
+            "bytes {}..{} of '{}' cut through a character", start, end, value))
+    }
+}
+
+fn bounds(len: usize, arguments: &[Value]) -> Result<(usize, usize), String> {
+    let index = |value: &Value, default: usize| -> Result<usize, String> {
+        let s = string(value)?;
+        if s.is_empty() {
+            return Ok(default);
+        }
+        match s.parse::<i64>() {
+            Ok(n) if n < 0 => Ok(len.saturating_sub(n.unsigned_abs() as usize)),
+            Ok(n) => Ok((n as usize).min(len)),
+            Err(_) => Err(format!("expected an integer index, got '{}'", s))
+        }
+    };
+    let start = index(&arguments[0], 0)?;
+    let end = match arguments.get(1) {
+        Some(end) => index(end, len)?,
+        None => len
+    };
+    Ok((start, end.max(start)))
+}
+
+fn join(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 1)?;
+    let separator = match arguments.first() {
+        Some(separator) => string(separator)?,
+        None => Cow::Borrowed(", ")
+    };
+    let list = match *value {
+        Value::List(ref list) => list,
+        _ => return Err("join expects a list".to_string())
+    };
+    let mut items = Vec::with_capacity(list.len());
+    for item in list {
+        items.push(string(item)?);
+    }
+    Ok(Value::String(items.join(&separator)))
+}
+
+// Indents every line but the first by the given number of spaces, or with
+// the given string. Empty lines are left empty.
+fn indent(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    let indentation = match unsigned(&arguments[0]) {
+        Ok(width) => " ".repeat(width),
+        Err(_) => string(&arguments[0])?.to_string()
+    };
+
+    let value = string(value)?;
+    let mut lines = value.split('\n');
+    let mut indented = lines.next().unwrap_or("").to_string();
+    for line in lines {
+        indented.push('\n');
+        if !line.is_empty() && line != "\r" {
+            indented.push_str(&indentation);
+        }
+        indented.push_str(line);
+    }
+    Ok(Value::String(indented))
+}
+
+fn hex(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.hex()))
+}
+
+fn hexa(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.hexa()))
+}
+
+// `argb("0x")` gives the `0xaarrggbb` form some applications expect.
+fn argb(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 1)?;
+    let argb = color(value)?.argb();
+    match arguments.first() {
+        Some(prefix) => Ok(Value::String(
+            format!("{}{}", string(prefix)?, &argb[1..]))),
+        None => Ok(Value::String(argb))
+    }
+}
+
+fn rgb(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.rgb()))
+}
+
+fn rgba(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.rgba()))
+}
+
+fn hsl(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.hsl()))
+}
+
+fn rgb_float(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.rgb_float()))
+}
+
+fn lighten(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    let color = color(value)?.lighten(fraction(&arguments[0])?);
+    Ok(Value::String(color.to_string()))
+}
+
+fn darken(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    let color = color(value)?.lighten(-fraction(&arguments[0])?);
+    Ok(Value::String(color.to_string()))
+}
+
+fn saturate(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    let color = color(value)?.saturate(fraction(&arguments[0])?);
+    Ok(Value::String(color.to_string()))
+}
+
+fn desaturate(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    let color = color(value)?.saturate(-fraction(&arguments[0])?);
+    Ok(Value::String(color.to_string()))
+}
+
+fn alpha(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 1)?;
+    let color = color(value)?.with_alpha(fraction(&arguments[0])?);
+    Ok(Value::String(color.to_string()))
+}
+
+fn invert(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 0, 0)?;
+    Ok(Value::String(color(value)?.invert().to_string()))
+}
+
+// Mixes in the given proportion of another color, half by default.
+fn mix(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    arity(arguments, 1, 2)?;
+    let weight = match arguments.get(1) {
+        Some(weight) => fraction(weight)?,
+        None => 0.5
+    };
+    let color = color(value)?.mix(&color(&arguments[0])?, weight);
+    Ok(Value::String(color.to_string()))
+}
+
+// Picks whichever of the two given colors, black and white by default, is
+// the most readable on top of the value.
+fn contrast(value: &Value, arguments: &[Value]) -> Result<Value, String> {
+    if !arguments.is_empty() && arguments.len() != 2 {
+        return Err(format!(
+            "expected 0 or 2 argument(s), got {}", arguments.len()));
+    }
+    let background = color(value)?;
+    let (dark, light) = match arguments.len() {
+        0 => (Color::parse("#000000")?, Color::parse("#ffffff")?),
+        _ => (color(&arguments[0])?, color(&arguments[1])?)
+    };
+    let picked = if background.contrast(&dark) >= background.contrast(&light) {
+        dark
+    }
+    else {
+        light
+    };
+    Ok(Value::String(picked.to_string()))
+}
+
+fn arity(arguments: &[Value], min: usize, max: usize) -> Result<(), String> {
+    if arguments.len() < min || arguments.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        }
+        else {
+            format!("{} to {}", min, max)
+        };
+        return Err(format!(
+            "expected {} argument(s), got {}", expected, arguments.len()));
+    }
+    Ok(())
+}
+
+fn string(value: &Value) -> Result<Cow<'_, str>, String> {
+    match value.as_text() {
+        Some(s) => Ok(s),
+        None => Err("expected a string, got a list or a map".to_string())
+    }
+}
+
+fn unsigned(value: &Value) -> Result<usize, String> {
+    if let Value::Integer(i) = *value {
+        if i >= 0 {
+            return Ok(i as usize);
+        }
+    }
+    let s = string(value)?;
+    match s.parse::<usize>() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("expected a positive integer, got '{}'", s))
+    }
+}
+
+fn color(value: &Value) -> Result<Color, String> {
+    Color::parse(&string(value)?)
+}
+
+// Reads `10%` or `0.1` as the same fraction.
+fn fraction(value: &Value) -> Result<f64, String> {
+    if value.is_number() {
+        return Ok(value.as_number().unwrap());
+    }
+    let s = string(value)?;
+    let (number, scale) = match s.strip_suffix('%') {
+        Some(percent) => (percent, 100.0),
+        None => (&s[..], 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(n) => Ok(n / scale),
+        Err(_) => Err(format!("expected a number or a percentage, got '{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(name: &str, value: &str, arguments: &[&str]) -> String {
+        let arguments: Vec<Value> = arguments.iter()
+            .map(|a| Value::String(a.to_string()))
+            .collect();
+        let result = lookup(name).unwrap()(
+            &Value::String(value.to_string()), &arguments);
+        match result.unwrap() {
+            Value::String(s) => s,
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(apply("upper", "#abc", &[]), "#ABC");
+        assert_eq!(apply("lower", "ÀB", &[]), "àb");
+        assert_eq!(apply("trim", "  a b ", &[]), "a b");
+        assert_eq!(apply("trim", "##a#", &["#"]), "a");
+        assert_eq!(apply("trim_hash", "#abcdef", &[]), "abcdef");
+        assert_eq!(apply("replace", "a-b-c", &["-", "+"]), "a+b+c");
+        assert_eq!(apply("prefix", "abc", &["0x"]), "0xabc");
+        assert_eq!(apply("suffix", "abc", &["ff"]), "abcff");
+        assert_eq!(apply("pad", "é", &["3"]), "é  ");
+        assert_eq!(apply("pad_left", "7", &["3", "0"]), "007");
+        assert_eq!(apply("pad_left", "1234", &["3"]), "1234");
+        assert_eq!(apply("indent", "a\nb\n\nc\n", &["2"]), "a\n  b\n\n  c\n");
+        assert_eq!(apply("indent", "a\r\nb", &["\t"]), "a\r\n\tb");
+    }
+
+    #[test]
+    fn test_slices() {
+        assert_eq!(apply("slice", "#abcdef", &["1"]), "abcdef");
+        assert_eq!(apply("slice", "#abcdef", &["1", "3"]), "ab");
+        assert_eq!(apply("slice", "#abcdef", &["-2"]), "ef");
+        assert_eq!(apply("slice", "#abcdef", &["", "-1"]), "#abcde");
+        assert_eq!(apply("slice", "#abcdef", &["1", "", "2"]), "ace");
+        assert_eq!(apply("slice", "#abcdef", &["10"]), "");
+        assert_eq!(apply("slice", "#abcdef", &["4", "2"]), "");
+        assert_eq!(apply("slice", "#abcdef", &["-10", "2"]), "#a");
+        assert_eq!(apply("slice", "éàü\u{f303} x", &["1", "4"]), "àü\u{f303}");
+        assert_eq!(apply("slice_bytes", "aé", &["0", "3"]), "aé");
+
+        let value = Value::String("aé".to_string());
+        let string = |s: &str| Value::String(s.to_string());
+        assert!(slice_bytes(&value, &[string("0"), string("2")]).is_err());
+        assert!(slice(&value, &[string("x")]).is_err());
+        assert!(slice(&value, &[string("0"), string(""), string("0")])
+            .is_err());
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(apply("quote", "say \"hi\"", &[]), "\"say \\\"hi\\\"\"");
+        assert_eq!(apply("json_escape", "a\"b\\\n\u{1}", &[]),
+            "a\\\"b\\\\\\n\\u0001");
+        assert_eq!(apply("shell_escape", "it's", &[]), "'it'\\''s'");
+        assert_eq!(apply("base64", "", &[]), "");
+        assert_eq!(apply("base64", "f", &[]), "Zg==");
+        assert_eq!(apply("base64", "fo", &[]), "Zm8=");
+        assert_eq!(apply("base64", "foobar", &[]), "Zm9vYmFy");
+        assert_eq!(apply("url_encode", "a b/é~", &[]), "a%20b%2F%C3%A9~");
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(apply("hex", "rgb(255, 0, 0)", &[]), "#ff0000");
+        assert_eq!(apply("rgb", "#ff0000", &[]), "rgb(255, 0, 0)");
+        assert_eq!(apply("argb", "#ff0000", &[]), "#ffff0000");
+        assert_eq!(apply("argb", "#ff000080", &["0x"]), "0x80ff0000");
+        assert_eq!(apply("lighten", "#336699", &["20%"]), "#6699cc");
+        assert_eq!(apply("darken", "#6699cc", &["0.2"]), "#336699");
+        assert_eq!(apply("desaturate", "#336699", &["100%"]), "#666666");
+        assert_eq!(apply("alpha", "#336699", &["50%"]), "#33669980");
+        assert_eq!(apply("mix", "#000000", &["#ffffff", "25%"]), "#404040");
+        assert_eq!(apply("contrast", "#202020", &[]), "#ffffff");
+        assert_eq!(apply("contrast", "#eeeeee", &["#111111", "#fafafa"]),
+            "#111111");
+        assert!(lookup("lighten").unwrap()(
+            &Value::String("#33669".to_string()),
+            &[Value::String("10%".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let value = Value::String("abc".to_string());
+        let arguments = [value.clone()];
+        let list = Value::List(vec![value.clone()]);
+
+        assert!(lookup("unknown").is_none());
+        assert!(upper(&value, &arguments).is_err());
+        assert!(upper(&list, &[]).is_err());
+        assert!(replace(&value, &arguments).is_err());
+        assert!(pad(&value, &arguments).is_err());
+        assert!(join(&value, &[]).is_err());
+        assert_eq!(join(&list, &[]), Ok(value));
+    }
+}
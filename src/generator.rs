@@ -1,186 +1,1031 @@
-use std::error::Error;
-use std::io::{BufReader, BufWriter};
+use std::io;
+use std::io::BufWriter;
 use std::io::prelude::*;
+use std::fs;
 use std::fs::File;
-use std::ops::Range;
-use std::ops::Index;
-use std::collections::HashMap;
+use std::os::unix;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 
-use config::PathBinding;
-use variables::Variables;
+use config::{Config, PathBinding};
+use variables::{Variables, Value};
+use template::{
+    Template, Node, Condition, Operand, Iterable, Position, Delimiters
+};
+use expression::{Expression, Arithmetic};
+use filters;
 
 #[derive(Debug)]
 pub struct Generator {
     variables: Variables,
+    options: Options
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub strict: bool
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub replacements: u32,
+    pub unresolved: Vec<(String, Position)>,
+    pub status: Status
+}
+
+// A rendered output is pending until it is written, or found unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Status {
+    Written,
+    Unchanged,
+    #[default]
+    Pending
+}
+
+// The innermost iteration of an enclosing `${{#each}}` block, exposed to its
+// body as `@index`, `@key`, `@value`, `@first` and `@last`.
+struct Frame {
+    index: Value,
+    key: Value,
+    value: Value,
+    first: Value,
+    last: Value
+}
+
+// The template being rendered, along with the templates which included it.
+struct Source<'a> {
+    path: &'a Path,
+    delimiters: &'a Delimiters,
+    parent: Option<&'a Source<'a>>
+}
+
+impl Report {
+    pub fn log(&self, binding: &PathBinding) {
+        let status = match self.status {
+            Status::Written => "written",
+            Status::Unchanged => "unchanged",
+            Status::Pending => "would be written"
+        };
+        info!("{}: replaced {} key(s), {} {}",
+            binding.from.display(), self.replacements,
+            binding.to.display(), status);
+        for &(ref key, position) in self.unresolved.iter() {
+            warn!("{}: {}: unresolved key '{}'",
+                binding.from.display(), position, key);
+        }
+    }
 }
 
 impl Generator {
-    pub fn new(variables: &HashMap<String, String>) -> Generator {
-        Generator {
-            variables: Variables::new(variables)
+    pub fn new(variables: &HashMap<String, Value>,
+        literals: &HashSet<String>, options: Options)
+        -> Result<Generator, String>
+    {
+        let variables = Variables::new(variables, literals);
+        if options.strict && !variables.problems().is_empty() {
+            return Err(format!("Invalid variables: {}",
+                variables.problems().join(", ")));
+        }
+        for problem in variables.problems() {
+            warn!("{}", problem);
         }
+
+        Ok(Generator {
+            variables,
+            options
+        })
     }
 
-    pub fn process(&self, binding: &PathBinding) -> Result<u32, String> {
+    pub fn process(&self, binding: &PathBinding) -> Result<Report, String> {
+        let (output, mut report) = self.generate(binding)?;
 
-        let from = match File::open(&binding.from) {
-            Ok(f) => BufReader::new(f),
+        // Rewriting an identical file would make every application watching
+        // it reload for nothing.
+        if let Ok(current) = fs::read(&binding.to) {
+            if current == output.as_bytes() {
+                report.status = Status::Unchanged;
+                return Ok(report);
+            }
+        }
+
+        // Bindings from a directory recreate the subdirectories of their
+        // templates.
+        let created = match binding.to.parent() {
+            Some(dir) if binding.expanded => fs::create_dir_all(dir),
+            _ => Ok(())
+        };
+        match created.and_then(
+            |_| Generator::write(&binding.to, output.as_bytes()))
+        {
+            Ok(_) => (),
             Err(e) => {
                 return Err(format!(
-                    "Couldn't open {}: {}",
-                    binding.from.display(),
-                    e.description()))
+                    "Error writing to {}: {}",
+                    binding.to.display(),
+                    e));
             }
+        }
+
+        report.status = Status::Written;
+        Ok(report)
+    }
+
+    // Renders a binding in memory, without touching its output file.
+    pub fn generate(&self, binding: &PathBinding)
+        -> Result<(String, Report), String>
+    {
+        let delimiters = binding.delimiters.clone().unwrap_or_default();
+        let template = self.load(&binding.from, &delimiters)?;
+        let source = Source {
+            path: &binding.from,
+            delimiters: &delimiters,
+            parent: None
         };
 
-        let mut to = match File::create(&binding.to) {
-            Ok(f) => BufWriter::new(f),
+        let mut output = String::new();
+        let mut report = Report::default();
+        if let Err(e) = self.render(
+            &template.nodes, &source, None, &mut output, &mut report)
+        {
+            return Err(format!("{}: {}", binding.from.display(), e));
+        }
+
+        Ok((output, report))
+    }
+
+    fn load(&self, path: &Path, delimiters: &Delimiters)
+        -> Result<Template, String>
+    {
+        let mut input = String::new();
+        let read = File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut input));
+        if let Err(e) = read {
+            return Err(format!(
+                "Error reading from {}: {}",
+                path.display(),
+                e));
+        }
+
+        let template = match Template::parse(&input, delimiters) {
+            Ok(t) => t,
             Err(e) => {
                 return Err(format!(
-                    "Couldn't open {}: {}",
-                    binding.to.display(),
-                    e.description()))
+                    "Couldn't parse {}: {}",
+                    path.display(),
+                    e))
             }
         };
 
+        for error in template.errors.iter() {
+            if self.options.strict {
+                return Err(format!("{}: {}", path.display(), error));
+            }
+            error!("{}: {}", path.display(), error);
+        }
 
-        let mut replacements = 0;
-        let mut output = String::new();
+        Ok(template)
+    }
 
-        for line in from.lines() {
+    // Writes to a temporary file next to the target, then renames it over
+    // the target, so that programs watching the target never see it empty or
+    // half written. The target's permissions and ownership are kept, and a
+    // symbolic link is followed rather than replaced.
+    fn write(path: &Path, content: &[u8]) -> io::Result<()> {
+        let target = match fs::symlink_metadata(path) {
+            Ok(ref m) if m.file_type().is_symlink() => path.canonicalize()?,
+            _ => path.to_path_buf()
+        };
+        let previous = fs::metadata(&target).ok();
 
-            let line = match line {
-                Ok(l) => l,
-                Err(e) => {
-                    return Err(format!(
-                        "Error reading from {}: {}",
-                        binding.from.display(),
-                        e.description()));
-                }
-            };
+        let dir = match target.parent() {
+            Some(d) if d != Path::new("") => d.to_path_buf(),
+            _ => PathBuf::from(".")
+        };
+        let file_name = match target.file_name() {
+            Some(f) => f.to_string_lossy().into_owned(),
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "not a file path"))
+        };
+        let temporary = dir.join(
+            format!(".{}.confy-{}", file_name, process::id()));
 
-            output.truncate(0);
-            replacements += self.process_line(&line, &mut output);
-            match to.write(output.as_bytes()) {
-                Ok(_) => (),
-                Err(e) => {
-                    return Err(format!(
-                        "Error writing to {}: {}",
-                        binding.to.display(),
-                        e.description()));
+        let written = File::create(&temporary).and_then(|file| {
+            let mut writer = BufWriter::new(&file);
+            writer.write_all(content)?;
+            writer.flush()?;
+            drop(writer);
+
+            if let Some(ref previous) = previous {
+                file.set_permissions(previous.permissions())?;
+                if let Err(e) = unix::fs::fchown(
+                    &file, Some(previous.uid()), Some(previous.gid()))
+                {
+                    warn!("Couldn't keep the ownership of {}: {}",
+                        target.display(), e);
                 }
             }
+
+            file.sync_all()
+        }).and_then(|_| fs::rename(&temporary, &target));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+            return written;
         }
 
-        Ok(replacements)
+        if let Err(e) = File::open(&dir).and_then(|d| d.sync_all()) {
+            debug!("Couldn't sync {}: {}", dir.display(), e);
+        }
+        Ok(())
     }
 
-    fn process_line(&self, input: &String, output: &mut String) -> u32 {
-        let mut remaining = &input[..];
-        let mut replacements = 0;
-
-        while let Some(start) = remaining.find("${{") {
-            match remaining.find("}}") {
-                Some(stop) => {
-                    output.push_str(&remaining[..start]);
-                    let expression = &remaining[start + 3..stop];
-                    match self.parse_expression(expression) {
+    fn render(&self, nodes: &[Node], source: &Source, frame: Option<&Frame>,
+        output: &mut String, report: &mut Report) -> Result<(), String>
+    {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => output.push_str(text),
+                Node::Substitution { ref expression, ref raw, position } => {
+                    let start = output.rfind('\n').map_or(0, |i| i + 1);
+                    let line = &output[start..];
+                    let value = self.substitute(
+                        expression, position, line, frame, report)?;
+                    match value {
                         Some(value) => {
                             output.push_str(&value);
-                            replacements += 1;
+                            report.replacements += 1;
                         },
-                        None => output.push_str(&remaining[start..stop + 2])
+                        None => output.push_str(raw)
+                    };
+                },
+                Node::Conditional {
+                    ref condition, ref consequent, ref alternative, position
+                } => {
+                    let holds = self.evaluate(condition, position, frame)?;
+                    let branch = if holds {
+                        consequent
+                    }
+                    else {
+                        alternative
                     };
-                    remaining = &remaining[stop + 2..];
+                    self.render(branch, source, frame, output, report)?;
+                },
+                Node::Loop { ref target, ref body, ref empty, position } => {
+                    let entries = self.iterate(target, position, frame)?;
+                    if entries.is_empty() {
+                        self.render(empty, source, frame, output, report)?;
+                    }
+
+                    let count = entries.len();
+                    let entries = entries.into_iter().enumerate();
+                    for (index, (key, value)) in entries {
+                        let inner = Frame {
+                            index: Value::Integer(index as i64),
+                            key: Value::String(key),
+                            value,
+                            first: Value::Bool(index == 0),
+                            last: Value::Bool(index + 1 == count)
+                        };
+                        self.render(
+                            body, source, Some(&inner), output, report)?;
+                    }
                 },
-                None => break
+                Node::Include { ref path, position } =>
+                    self.include(path, position, source, frame, output, report)?
+            }
+        }
+
+        Ok(())
+    }
+
+    // Partials are resolved relative to the template including them, and
+    // rendered with the same variables and loop iteration.
+    fn include(&self, path: &str, position: Position, source: &Source,
+        frame: Option<&Frame>, output: &mut String, report: &mut Report)
+        -> Result<(), String>
+    {
+        let path = Config::resolve_path(Path::new(path), source.path.parent());
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+
+        let mut ancestor = Some(source);
+        while let Some(a) = ancestor {
+            if a.path.canonicalize().ok().as_ref() == Some(&canonical) {
+                return Err(format!("{}: {} includes itself",
+                    position, path.display()));
             }
+            ancestor = a.parent;
+        }
+
+        let template = match self.load(&path, source.delimiters) {
+            Ok(t) => t,
+            Err(e) => return Err(format!("{}: {}", position, e))
         };
+        let inner = Source {
+            path: &path,
+            delimiters: source.delimiters,
+            parent: Some(source)
+        };
+
+        match self.render(&template.nodes, &inner, frame, output, report) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!(
+                "{}: in {}: {}", position, path.display(), e))
+        }
+    }
 
-        output.push_str(remaining);
-        output.push('\n');
+    fn iterate(&self, target: &Iterable, position: Position,
+        frame: Option<&Frame>) -> Result<Vec<(String, Value)>, String>
+    {
+        let mut entries: Vec<(String, Value)> = match *target {
+            Iterable::Variable(ref key) => match self.lookup(key, frame) {
+                Some(Value::List(list)) => list.iter()
+                    .enumerate()
+                    .map(|(i, value)| (i.to_string(), value.clone()))
+                    .collect(),
+                Some(Value::Map(map)) => map.iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect(),
+                Some(_) => {
+                    self.fail(position,
+                        format!("'{}' is neither a list nor a map", key))?;
+                    Vec::new()
+                },
+                None => {
+                    self.fail(position, format!("unresolved key '{}'", key))?;
+                    Vec::new()
+                }
+            },
+            // Only the direct children, nested maps and lists being entries
+            // of their own.
+            Iterable::Prefix(ref prefix) => self.variables.iter()
+                .filter_map(|(key, value)| key.strip_prefix(&prefix[..])
+                    .filter(|suffix| !suffix.contains('.'))
+                    .map(|suffix| (suffix.to_string(), value.clone())))
+                .collect()
+        };
 
-        replacements
+        entries.sort_by(|a, b| Value::compare_keys(&a.0, &b.0));
+        Ok(entries)
     }
 
-    fn parse_expression(&self, expression: &str) -> Option<String> {
+    fn lookup<'b>(&'b self, key: &str, frame: Option<&'b Frame>)
+        -> Option<&'b Value>
+    {
+        if !key.starts_with('@') {
+            return self.variables.get(key);
+        }
 
-        let mut key = expression;
-        let range_str = match expression.find("[") {
-            Some(start) => {
-                match expression.find("]") {
-                    Some(stop) => {
-                        key = &expression[..start];
-                        Some(&expression[start + 1..stop])
-                    },
-                    None => return None
-                }
+        let frame = frame?;
+        let mut path = key.split('.');
+        let mut value = match path.next() {
+            Some("@index") => &frame.index,
+            Some("@key") => &frame.key,
+            Some("@value") => &frame.value,
+            Some("@first") => &frame.first,
+            Some("@last") => &frame.last,
+            _ => return None
+        };
+
+        for part in path {
+            value = value.get(part)?;
+        }
+
+        Some(value)
+    }
+
+    // A key alone tests whether it is defined and true, see
+    // `Value::is_truthy`. Comparisons involving an undefined key are false,
+    // except for `!=`. Values are compared as numbers when one of them is a
+    // number, or when ordering them, and as text otherwise.
+    fn evaluate(&self, condition: &Condition, position: Position,
+        frame: Option<&Frame>) -> Result<bool, String>
+    {
+        let (a, b) = match *condition {
+            Condition::Defined(ref operand) => {
+                return Ok(self.operand(operand, position, frame)?
+                    .map(|value| value.is_truthy())
+                    .unwrap_or(false));
             },
-            None => None
-        };
-        
-        let value = match self.variables.get(key) {
-            Some(value) => match range_str {
-                Some(range_str) => match self.parse_range(value, range_str) {
-                    Some(range) => {
-                        let value = value.index(range.clone());
-                        debug!("{}[{:?}] = {}", key, range, value);
-                        value
+            Condition::Equal(ref a, ref b)
+                | Condition::NotEqual(ref a, ref b)
+                | Condition::Less(ref a, ref b)
+                | Condition::LessOrEqual(ref a, ref b)
+                | Condition::Greater(ref a, ref b)
+                | Condition::GreaterOrEqual(ref a, ref b) => (a, b)
+        };
+
+        let values = (
+            self.operand(a, position, frame)?,
+            self.operand(b, position, frame)?
+        );
+        let (a, b) = match values {
+            (Some(a), Some(b)) => (a, b),
+            _ => {
+                for operand in [a, b].iter() {
+                    if let Some(key) = self.missing(operand, frame) {
+                        self.fail(position,
+                            format!("unresolved key '{}'", key))?;
                     }
-                    None => return None
-                },
-                None => value
-            },
-            None => return None
+                }
+                return Ok(matches!(*condition, Condition::NotEqual(..)));
+            }
+        };
+        let (a, b) = (&*a, &*b);
+
+        let numbers = match (a.as_number(), b.as_number()) {
+            (Some(x), Some(y)) => Some((x, y)),
+            _ => None
+        };
+        let equal = match (numbers, a.as_text(), b.as_text()) {
+            (Some((x, y)), _, _) if a.is_number() || b.is_number() => x == y,
+            (_, Some(x), Some(y)) => x == y,
+            _ => a == b
         };
 
-        Some(value.to_string())
+        let ordering = match *condition {
+            Condition::Equal(..) => return Ok(equal),
+            Condition::NotEqual(..) => return Ok(!equal),
+            _ => match numbers {
+                Some((x, y)) => x.partial_cmp(&y),
+                None => None
+            }
+        };
+        let ordering = match ordering {
+            Some(o) => o,
+            None => {
+                self.fail(position, format!(
+                    "cannot order '{}' and '{}'", Generator::describe(a),
+                    Generator::describe(b)))?;
+                return Ok(false);
+            }
+        };
+
+        Ok(match *condition {
+            Condition::Less(..) => ordering == Ordering::Less,
+            Condition::LessOrEqual(..) => ordering != Ordering::Greater,
+            Condition::Greater(..) => ordering == Ordering::Greater,
+            _ => ordering != Ordering::Less
+        })
     }
 
-    fn parse_range(&self, value: &str, range: &str) -> Option<Range<usize>> {
-        match range.find("..") {
-            Some(dot) => {
-                let start = {
-                    if dot == 0 {
-                        0
-                    }
-                    else {
-                        match range[..dot].parse::<usize>() {
-                            Ok(start) => {
-                                if start >= value.len() {
-                                    value.len() - 1
-                                }
-                                else {
-                                    start
-                                }
-                            },
-                            Err(_) => return None
-                        }
-                    }
+    fn describe(value: &Value) -> String {
+        match value.as_text() {
+            Some(text) => text.into_owned(),
+            None => "a list or a map".to_string()
+        }
+    }
+
+    // The value of an operand, or `None` when it depends on an undefined
+    // key or cannot be calculated.
+    fn operand<'b>(&'b self, operand: &'b Operand, position: Position,
+        frame: Option<&'b Frame>) -> Result<Option<Cow<'b, Value>>, String>
+    {
+        let arithmetic = match *operand {
+            Operand::Variable(ref key) =>
+                return Ok(self.lookup(key, frame).map(Cow::Borrowed)),
+            Operand::Literal(ref literal) =>
+                return Ok(Some(Cow::Borrowed(literal))),
+            Operand::Arithmetic(ref arithmetic) => arithmetic
+        };
+
+        match self.calculate(arithmetic, position, frame) {
+            Ok(Some(n)) if n.fract() == 0.0 && n.abs() < 1e15 =>
+                Ok(Some(Cow::Owned(Value::Integer(n as i64)))),
+            Ok(Some(n)) => Ok(Some(Cow::Owned(Value::Float(n)))),
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.fail(position, e)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn calculate(&self, arithmetic: &Arithmetic, position: Position,
+        frame: Option<&Frame>) -> Result<Option<f64>, String>
+    {
+        let n = match *arithmetic {
+            Arithmetic::Operand(ref operand) => {
+                let value = match self.operand(operand, position, frame)? {
+                    Some(v) => v,
+                    None => return Ok(None)
                 };
-                let end = {
-                    if dot + 2 == range.len() {
-                        value.len()
+                match value.as_number() {
+                    Some(n) => n,
+                    None => return Err(format!("'{}' is not a number",
+                        Generator::describe(&value)))
+                }
+            },
+            Arithmetic::Negate(ref a) =>
+                match self.calculate(a, position, frame)? {
+                    Some(n) => -n,
+                    None => return Ok(None)
+                },
+            Arithmetic::Binary(operator, ref a, ref b) => {
+                let a = self.calculate(a, position, frame)?;
+                let b = self.calculate(b, position, frame)?;
+                match (a, b) {
+                    (Some(a), Some(b)) => operator.apply(a, b)?,
+                    _ => return Ok(None)
+                }
+            },
+            Arithmetic::Call(function, ref arguments) => {
+                let mut values = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    match self.calculate(argument, position, frame)? {
+                        Some(n) => values.push(n),
+                        None => return Ok(None)
                     }
-                    else {
-                        debug!("end: {}", &range[dot + 2..]);
-                        match range[dot + 2..].parse::<usize>() {
-                            Ok(end) => {
-                                if end > value.len() {
-                                    value.len()
-                                }
-                                else {
-                                    end
-                                }
-                            },
-                            Err(_) => return None
-                        }
+                }
+                function.apply(&values)?
+            }
+        };
+
+        if n.is_finite() {
+            Ok(Some(n))
+        }
+        else {
+            Err("the result is not a finite number".to_string())
+        }
+    }
+
+    // The first undefined key an operand depends on.
+    fn missing(&self, operand: &Operand, frame: Option<&Frame>)
+        -> Option<String>
+    {
+        match *operand {
+            Operand::Variable(ref key) => Some(key.clone())
+                .filter(|key| self.lookup(key, frame).is_none()),
+            Operand::Literal(_) => None,
+            Operand::Arithmetic(ref arithmetic) => arithmetic.variables()
+                .into_iter()
+                .find(|key| self.lookup(key, frame).is_none())
+                .map(str::to_string)
+        }
+    }
+
+    // Evaluates an expression, or returns `None` when it must be left as is
+    // in the output. `line` is what precedes the expression on its output
+    // line, from which `indent` without arguments takes its column.
+    fn substitute(&self, expression: &Expression, position: Position,
+        line: &str, frame: Option<&Frame>, report: &mut Report)
+        -> Result<Option<String>, String>
+    {
+        let mut found = None;
+        for operand in expression.alternatives.iter() {
+            found = self.operand(operand, position, frame)?;
+            if found.is_some() {
+                break;
+            }
+        }
+        let mut value = match found {
+            Some(value) => value.into_owned(),
+            None => {
+                let missing = expression.alternatives.iter()
+                    .filter_map(|operand| self.missing(operand, frame))
+                    .collect();
+                self.unresolved(missing, position, report)?;
+                return Ok(None);
+            }
+        };
+
+        for filter in expression.filters.iter() {
+            let mut arguments = Vec::with_capacity(filter.arguments.len());
+            for argument in filter.arguments.iter() {
+                match self.operand(argument, position, frame)? {
+                    Some(value) => arguments.push(value.into_owned()),
+                    None => {
+                        let missing = self.missing(argument, frame);
+                        self.unresolved(missing.into_iter().collect(),
+                            position, report)?;
+                        return Ok(None);
                     }
-                };
-                
-                Some(Range { start, end })
-            },
-            None => None
+                }
+            }
+            if filter.name == "indent" && arguments.is_empty() {
+                let indentation = line.chars()
+                    .map(|c| if c.is_whitespace() { c } else { ' ' })
+                    .collect();
+                arguments.push(Value::String(indentation));
+            }
+            let function = match filters::lookup(&filter.name) {
+                Some(f) => f,
+                None => return Ok(None)
+            };
+            value = match function(&value, &arguments) {
+                Ok(v) => v,
+                Err(e) => {
+                    self.fail(position, format!("{}: {}", filter.name, e))?;
+                    return Ok(None);
+                }
+            };
+        }
+
+        match value.as_text() {
+            Some(text) => Ok(Some(text.into_owned())),
+            None => {
+                self.fail(position,
+                    "a list or a map cannot be substituted".to_string())?;
+                Ok(None)
+            }
+        }
+    }
+
+    // Reports every key tried in vain for an expression.
+    fn unresolved(&self, keys: Vec<String>, position: Position,
+        report: &mut Report) -> Result<(), String>
+    {
+        if self.options.strict && !keys.is_empty() {
+            let keys: Vec<String> = keys.iter()
+                .map(|key| format!("'{}'", key))
+                .collect();
+            let plural = if keys.len() > 1 { "s" } else { "" };
+            return Err(format!("{}: unresolved key{} {}",
+                position, plural, keys.join(", ")));
+        }
+        for key in keys {
+            report.unresolved.push((key, position));
+        }
+        Ok(())
+    }
+
+    // Reports a problem which is fatal in strict mode, and which otherwise
+    // leaves the expression as is in the output.
+    fn fail(&self, position: Position, message: String) -> Result<(), String> {
+        if self.options.strict {
+            return Err(format!("{}: {}", position, message));
+        }
+        warn!("{}: {}", position, message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempDir;
+    use std::collections::BTreeMap;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn render_with(input: &str, map: &HashMap<String, Value>,
+        options: Options) -> Result<(String, Report), String>
+    {
+        let generator = Generator::new(map, &HashSet::new(), options)?;
+        let delimiters = Delimiters::default();
+        let template = Template::parse(input, &delimiters).unwrap();
+        let source = Source {
+            path: Path::new("test.cfy"),
+            delimiters: &delimiters,
+            parent: None
+        };
+        let mut output = String::new();
+        let mut report = Report::default();
+        generator.render(
+            &template.nodes, &source, None, &mut output, &mut report)?;
+        Ok((output, report))
+    }
+
+    fn render_map(input: &str, map: &HashMap<String, Value>) -> String {
+        render_with(input, map, Options::default()).unwrap().0
+    }
+
+    fn render(input: &str, pairs: &[(&str, &str)]) -> String {
+        let mut map = HashMap::new();
+        for &(key, value) in pairs {
+            map.insert(key.to_string(), string(value));
+        }
+        render_map(input, &map)
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let input = "\
+            ${{#if compositor == 'picom'}}\n\
+            backend = \"${{backend}}\"\n\
+            ${{else}}\n\
+            # no compositor\n\
+            ${{/if}}\n";
+        let pairs = [("compositor", "picom"), ("backend", "glx")];
+
+        assert_eq!(render(input, &pairs), "backend = \"glx\"\n");
+        assert_eq!(render(input, &pairs[1..]), "# no compositor\n");
+        assert_eq!(
+            render("${{#if a != b}}x${{/if}}", &[("a", "1"), ("b", "1")]),
+            "");
+        assert_eq!(
+            render("${{#if unset}}x${{else}}y${{/if}}", &[]),
+            "y");
+    }
+
+    #[test]
+    fn test_loops() {
+        let mut map = HashMap::new();
+        for i in 0..11 {
+            map.insert(format!("confy.color.{}", i), string(&i.to_string()));
         }
+        map.insert("confy.color.primary".to_string(), string("@confy.color.3"));
+        map.insert("fonts".to_string(),
+            Value::List(vec![string("Hack"), string("Noto")]));
+        let mut palette = BTreeMap::new();
+        palette.insert("bg".to_string(), string("#000"));
+        palette.insert("fg".to_string(), string("#fff"));
+        map.insert("palette".to_string(), Value::Map(palette));
+
+        assert_eq!(
+            render_map("${{#each confy.color.*}}${{@key}}=${{@value}} ${{/each}}", &map),
+            "0=0 1=1 2=2 3=3 4=4 5=5 6=6 7=7 8=8 9=9 10=10 primary=3 ");
+        assert_eq!(
+            render_map("${{#each fonts}}${{@index}}:${{@value}}\
+                ${{#if @last}}.${{else}}, ${{/if}}${{/each}}", &map),
+            "0:Hack, 1:Noto.");
+        assert_eq!(
+            render_map("${{#each fonts}}${{@first}} ${{@last}} \
+                ${{#if @index >= 1}}${{@index | pad_left(2, 0)}}${{/if}}\n\
+                ${{/each}}", &map),
+            "true false \nfalse true 01\n");
+        assert_eq!(
+            render_map("${{#each palette}}${{@key}} ${{@value}}\n${{/each}}", &map),
+            "bg #000\nfg #fff\n");
+        assert_eq!(
+            render_map("${{#each missing}}x${{else}}empty${{/each}}", &map),
+            "empty");
+
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(
+            "{color: {primary: '#abcdef', bright: ['#ffffff', '#000000']}}")
+            .unwrap();
+        assert_eq!(
+            render_map("${{#each color.*}}${{@key}} ${{/each}}", &map),
+            "bright primary ");
+        assert_eq!(
+            render_map("${{#each color.*}}${{#if @key == 'bright'}}\
+                ${{#each @value}}${{@index}}=${{@value}} ${{/each}}\
+                ${{/if}}${{/each}}", &map),
+            "0=#ffffff 1=#000000 ");
+    }
+
+    #[test]
+    fn test_filters() {
+        let pairs = [("confy.color", "#a0b1c2"), ("confy.name", "a b")];
+
+        assert_eq!(
+            render("${{confy.color | trim_hash | upper}}", &pairs),
+            "A0B1C2");
+        assert_eq!(
+            render("${{confy.color[1..3] | prefix('0x')}}", &pairs),
+            "0xa0");
+        assert_eq!(
+            render("${{confy.name | replace(' ', confy.color)}}", &pairs),
+            "a#a0b1c2b");
+        assert_eq!(
+            render("${{confy.name | pad(x)}}", &pairs),
+            "${{confy.name | pad(x)}}");
+    }
+
+    #[test]
+    fn test_scalars() {
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(
+            "{size: 11, scale: 1.5, on: true, off: false, none: ~, n: '9'}")
+            .unwrap();
+
+        assert_eq!(render_map("${{size}} ${{scale}} ${{on}} [${{none}}]", &map),
+            "11 1.5 true []");
+        assert_eq!(render_map("${{size | pad_left(4, 0)}}", &map), "0011");
+        assert_eq!(render_map("${{#if off}}x${{else}}y${{/if}}", &map), "y");
+        assert_eq!(render_map("${{#if on == true}}x${{/if}}", &map), "x");
+        assert_eq!(render_map("${{#if size == 11.0}}x${{/if}}", &map), "x");
+        assert_eq!(render_map("${{#if size == '11'}}x${{/if}}", &map), "x");
+        assert_eq!(render_map("${{#if size > n}}x${{/if}}", &map), "x");
+        assert_eq!(render_map("${{#if scale<=1}}x${{else}}y${{/if}}", &map),
+            "y");
+        assert_eq!(render_map("${{#if none == null}}x${{/if}}", &map), "x");
+
+        let strict = Options { strict: true };
+        assert!(render_with("${{#if on < 1}}x${{/if}}", &map, strict).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(
+            "{gap: 4, font.size: 11, padding: '3', opacity: 0.8, name: x, \
+            path/to: p, a+b: s}").unwrap();
+
+        assert_eq!(render_map("${{gap * 2}}", &map), "8");
+        assert_eq!(render_map("${{gap -1}} ${{gap*2 + 1}}", &map), "3 9");
+        assert_eq!(render_map("${{path/to}} ${{a+b | upper}}", &map), "p S");
+        assert_eq!(render_map("${{font.size + padding * 2}}", &map), "17");
+        assert_eq!(render_map("${{(font.size + padding) / 4}}", &map), "3.5");
+        assert_eq!(render_map("${{- gap % 3}}", &map), "-1");
+        assert_eq!(render_map("${{round(opacity * 255)}}", &map), "204");
+        assert_eq!(render_map("${{clamp(gap * 100, 0, 255)}}", &map), "255");
+        assert_eq!(render_map("${{min(gap, font.size) | pad_left(3, 0)}}", &map),
+            "004");
+        assert_eq!(render_map("${{#if gap * 3 > font.size}}x${{/if}}", &map),
+            "x");
+        assert_eq!(render_map("${{unset * 2 ?? gap + 1}}", &map), "5");
+        assert_eq!(render_map("${{name + 1}}", &map), "${{name + 1}}");
+
+        let strict = Options { strict: true };
+        let error = render_with("${{gap / (gap - 4)}}", &map, strict.clone())
+            .unwrap_err();
+        assert_eq!(error, "line 1, column 1: division by zero");
+        let error = render_with("${{unset + 1}}", &map, strict).unwrap_err();
+        assert_eq!(error, "line 1, column 1: unresolved key 'unset'");
+    }
+
+    #[test]
+    fn test_indent() {
+        let pairs = [("confy.fonts", "- Hack\n- Fira\n")];
+
+        assert_eq!(
+            render("fonts:\n  list: ${{confy.fonts | indent}}end", &pairs),
+            "fonts:\n  list: - Hack\n        - Fira\nend");
+        assert_eq!(
+            render("\tx = ${{confy.fonts | trim | indent}}", &pairs),
+            "\tx = - Hack\n\t    - Fira");
+        assert_eq!(
+            render("x = ${{confy.fonts | indent(1)}}", &pairs),
+            "x = - Hack\n - Fira\n");
+    }
+
+    #[test]
+    fn test_escapes() {
+        let pairs = [("confy.path", "/bin")];
+
+        assert_eq!(render("PATH=$${{confy.path}}", &pairs),
+            "PATH=${{confy.path}}");
+        assert_eq!(render("PATH=${{'$'}}${{confy.path}}", &pairs),
+            "PATH=$/bin");
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let pairs = [("confy.font", "Hack"), ("confy.empty", "")];
+
+        assert_eq!(
+            render("${{confy.size ?? confy.font | upper}}", &pairs), "HACK");
+        assert_eq!(render("${{confy.size ?? 11}}pt", &pairs), "11pt");
+        assert_eq!(render("${{confy.empty ?? 'x'}}", &pairs), "");
+        assert_eq!(
+            render("${{confy.size ?? confy.other}}", &pairs),
+            "${{confy.size ?? confy.other}}");
+    }
+
+    #[test]
+    fn test_strict() {
+        let mut map = HashMap::new();
+        map.insert("confy.font".to_string(), string("Hack"));
+        let strict = Options { strict: true };
+        let input = "${{confy.font}}\n  ${{confy.size}}\n";
+
+        let (output, report) = render_with(input, &map, Options::default())
+            .unwrap();
+        assert_eq!(output, "Hack\n  ${{confy.size}}\n");
+        assert_eq!(report.replacements, 1);
+        assert_eq!(report.unresolved, vec![
+            ("confy.size".to_string(), Position { line: 2, column: 3 })
+        ]);
+
+        assert_eq!(
+            render_with(input, &map, strict.clone()).unwrap_err(),
+            "line 2, column 3: unresolved key 'confy.size'");
+        assert!(render_with("${{confy.font | pad(x)}}", &map, strict.clone())
+            .is_err());
+        assert_eq!(
+            render_with("${{confy.size ?? confy.other ?? 1 + confy.gap}}",
+                &map, strict.clone()).unwrap_err(),
+            "line 1, column 1: unresolved keys 'confy.size', 'confy.other', \
+            'confy.gap'");
+        let (_, report) = render_with("${{confy.size ?? confy.other}}", &map,
+            Options::default()).unwrap();
+        assert_eq!(report.unresolved, vec![
+            ("confy.size".to_string(), Position { line: 1, column: 1 }),
+            ("confy.other".to_string(), Position { line: 1, column: 1 })
+        ]);
+        assert!(render_with("${{#if confy.size == 1}}${{/if}}", &map,
+            strict.clone()).is_err());
+        assert!(render_with("${{#each confy.list}}${{/each}}", &map,
+            strict.clone()).is_err());
+        assert!(render_with("${{#if confy.size}}${{/if}}", &map,
+            strict.clone()).is_ok());
+
+        map.insert("confy.theme".to_string(), string("@confy.missing"));
+        assert!(render_with("${{confy.font}}", &map, Options::default())
+            .is_ok());
+        assert_eq!(render_with("${{confy.font}}", &map, strict).unwrap_err(),
+            "Invalid variables: Variable confy.theme references undefined \
+            key 'confy.missing'");
+    }
+
+    #[test]
+    fn test_write() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = TempDir::new("write");
+        let target = dir.join("target.conf");
+        let link = dir.join("link.conf");
+
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640))
+            .unwrap();
+        symlink(&target, &link).unwrap();
+
+        Generator::write(&link, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(
+            fs::metadata(&target).unwrap().permissions().mode() & 0o777,
+            0o640);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        assert!(Generator::write(&dir.join("missing/x.conf"), b"").is_err());
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let dir = TempDir::new("unchanged");
+        let binding = PathBinding {
+            from: dir.join("a.cfy"),
+            to: dir.join("a.conf"),
+            delimiters: None,
+            expanded: false
+        };
+        fs::write(&binding.from, "font = ${{confy.font}}\n").unwrap();
+
+        let mut map = HashMap::new();
+        map.insert("confy.font".to_string(), string("Hack"));
+        let generator = Generator::new(&map, &HashSet::new(),
+            Options::default()).unwrap();
+
+        let (_, report) = generator.generate(&binding).unwrap();
+        assert_eq!(report.status, Status::Pending);
+        assert!(!binding.to.exists());
+
+        let report = generator.process(&binding).unwrap();
+        assert_eq!(report.status, Status::Written);
+        let modified = fs::metadata(&binding.to).unwrap().modified().unwrap();
+
+        let report = generator.process(&binding).unwrap();
+        assert_eq!(report.status, Status::Unchanged);
+        assert_eq!(report.replacements, 1);
+        assert_eq!(
+            fs::metadata(&binding.to).unwrap().modified().unwrap(),
+            modified);
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = TempDir::new("includes");
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        let binding = PathBinding {
+            from: dir.join("a.cfy"),
+            to: dir.join("a.conf"),
+            delimiters: None,
+            expanded: false
+        };
+        fs::write(&binding.from,
+            "${{#each fonts}}\n${{> partials/font.cfy}}\n${{/each}}\n")
+            .unwrap();
+        fs::write(dir.join("partials/font.cfy"),
+            "font = ${{@value}}${{> size.cfy}}\n").unwrap();
+        fs::write(dir.join("partials/size.cfy"), " ${{size}}").unwrap();
+
+        let mut map = HashMap::new();
+        map.insert("fonts".to_string(),
+            Value::List(vec![string("Hack"), string("Fira")]));
+        map.insert("size".to_string(), string("11"));
+        let generator = Generator::new(&map, &HashSet::new(),
+            Options::default()).unwrap();
+
+        let (output, report) = generator.generate(&binding).unwrap();
+        assert_eq!(output, "font = Hack 11\nfont = Fira 11\n");
+        assert_eq!(report.replacements, 4);
+
+        fs::write(dir.join("partials/size.cfy"), "${{> font.cfy}}").unwrap();
+        let cycle = generator.generate(&binding).unwrap_err();
+        assert!(cycle.contains("font.cfy includes itself"), "{}", cycle);
+
+        fs::remove_file(dir.join("partials/size.cfy")).unwrap();
+        assert!(generator.generate(&binding).is_err());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let pairs = [("confy.a", "x")];
+
+        assert_eq!(
+            render("${{confy.a}}\r\nb\r\nc", &pairs),
+            "x\r\nb\r\nc");
+        assert_eq!(
+            render("${{#if confy.a}}\r\na\r\n${{/if}}\r\nb", &pairs),
+            "a\r\nb");
+        assert_eq!(render("a\nb", &pairs), "a\nb");
+        assert_eq!(render("a\r\n\n\r\n", &pairs), "a\r\n\n\r\n");
     }
 }
@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const EXTENSION: &str = "cfy";
+
+
+// Templates found under `base` whose relative path matches `components`,
+// each of them a name with `*` and `?` wildcards or `**` for any number of
+// directories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub base: PathBuf,
+    components: Vec<String>
+}
+
+impl Pattern {
+    // A path with wildcards, or a directory standing for every template
+    // below it. Anything else is a single file.
+    pub fn new(path: &Path) -> Option<Pattern> {
+        let mut base = PathBuf::new();
+        let mut components = Vec::new();
+        for component in path.components() {
+            let name = component.as_os_str().to_string_lossy();
+            if components.is_empty() && !name.contains(['*', '?']) {
+                base.push(component);
+            }
+            else {
+                components.push(name.into_owned());
+            }
+        }
+
+        if components.is_empty() {
+            if !path.is_dir() {
+                return None;
+            }
+            components.push("**".to_string());
+            components.push(format!("*.{}", EXTENSION));
+        }
+        Some(Pattern { base, components })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        match self.relative(path) {
+            Some(names) => Pattern::matches_all(&self.components, &names),
+            None => false
+        }
+    }
+
+    // The path of `file` below `base` where the `.cfy` extension is dropped,
+    // to be recreated in the destination directory.
+    pub fn destination(&self, file: &Path, to: &Path) -> PathBuf {
+        let path = to.join(file.strip_prefix(&self.base).unwrap_or(file));
+        match path.extension() {
+            Some(extension) if extension == EXTENSION =>
+                path.with_extension(""),
+            _ => path
+        }
+    }
+
+    // Every matching file, and every directory which may hold one, in a
+    // stable order.
+    pub fn walk(&self) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        if self.base.as_os_str().is_empty() {
+            self.visit(Path::new("."), &mut files, &mut dirs);
+        }
+        else if self.base.is_dir() {
+            self.visit(&self.base, &mut files, &mut dirs);
+        }
+        (files, dirs)
+    }
+
+    fn visit(&self, dir: &Path, files: &mut Vec<PathBuf>,
+        dirs: &mut Vec<PathBuf>)
+    {
+        dirs.push(dir.to_path_buf());
+        let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect(),
+            Err(e) => {
+                warn!("Couldn't read {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        entries.sort();
+
+        for path in entries {
+            let names = match self.relative(&path) {
+                Some(names) => names,
+                None => continue
+            };
+            // Linked directories are not followed, they could loop.
+            let is_link = fs::symlink_metadata(&path)
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false);
+            if path.is_dir() {
+                if !is_link && Pattern::matches_start(&self.components, &names)
+                {
+                    self.visit(&path, files, dirs);
+                }
+            }
+            else if Pattern::matches_all(&self.components, &names) {
+                files.push(path);
+            }
+        }
+    }
+
+    fn relative(&self, path: &Path) -> Option<Vec<String>> {
+        let mut names = Vec::new();
+        for component in path.strip_prefix(&self.base).ok()?.components() {
+            match component {
+                Component::Normal(name) =>
+                    names.push(name.to_string_lossy().into_owned()),
+                Component::CurDir => (),
+                _ => return None
+            };
+        }
+        Some(names)
+    }
+
+    fn matches_all(components: &[String], names: &[String]) -> bool {
+        match components.split_first() {
+            None => names.is_empty(),
+            Some((first, rest)) if first == "**" => (0..names.len() + 1)
+                .take_while(|&i| i == 0 || !names[i - 1].starts_with('.'))
+                .any(|i| Pattern::matches_all(rest, &names[i..])),
+            Some((first, rest)) => match names.split_first() {
+                Some((name, names)) => Pattern::matches_name(first, name)
+                    && Pattern::matches_all(rest, names),
+                None => false
+            }
+        }
+    }
+
+    // Whether the directory `names` may hold matching files.
+    fn matches_start(components: &[String], names: &[String]) -> bool {
+        match (components.split_first(), names.split_first()) {
+            (_, None) => true,
+            (None, _) => false,
+            (Some((first, _)), Some((name, _))) if first == "**" =>
+                !name.starts_with('.'),
+            (Some((first, rest)), Some((name, names))) =>
+                Pattern::matches_name(first, name)
+                    && Pattern::matches_start(rest, names)
+        }
+    }
+
+    // Wildcards don't match the leading dot of hidden files, such as the
+    // ones editors leave next to the file being edited, and `**` doesn't go
+    // through hidden directories.
+    fn matches_name(pattern: &str, name: &str) -> bool {
+        fn matches(pattern: &[char], name: &[char]) -> bool {
+            match pattern.split_first() {
+                None => name.is_empty(),
+                Some((&'*', rest)) => (0..name.len() + 1)
+                    .any(|i| matches(rest, &name[i..])),
+                Some((&'?', rest)) => !name.is_empty()
+                    && matches(rest, &name[1..]),
+                Some((c, rest)) => name.first() == Some(c)
+                    && matches(rest, &name[1..])
+            }
+        }
+
+        if name.starts_with('.') && !pattern.starts_with('.') {
+            return false;
+        }
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        matches(&pattern, &name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempDir;
+
+    #[test]
+    fn test_matches_name() {
+        assert!(Pattern::matches_name("*.cfy", "a.conf.cfy"));
+        assert!(Pattern::matches_name("a?c", "abc"));
+        assert!(Pattern::matches_name("*", ""));
+        assert!(Pattern::matches_name(".*", ".hidden"));
+        assert!(!Pattern::matches_name("*.cfy", ".#a.cfy"));
+        assert!(!Pattern::matches_name("a?c", "ac"));
+        assert!(!Pattern::matches_name("*.cfy", "a.cfy.swp"));
+    }
+
+    #[test]
+    fn test_walk() {
+        let dir = TempDir::new("glob");
+        fs::create_dir_all(dir.join("themes/nord/bar")).unwrap();
+        fs::create_dir_all(dir.join("themes/.git")).unwrap();
+        fs::write(dir.join("themes/a.conf.cfy"), "").unwrap();
+        fs::write(dir.join("themes/nord/b.cfy"), "").unwrap();
+        fs::write(dir.join("themes/nord/bar/c.cfy"), "").unwrap();
+        fs::write(dir.join("themes/nord/bar/d.txt"), "").unwrap();
+        fs::write(dir.join("themes/.git/e.cfy"), "").unwrap();
+
+        let pattern = Pattern::new(&dir.join("themes/**/*.cfy")).unwrap();
+        assert_eq!(pattern.base, dir.join("themes"));
+        let (files, dirs) = pattern.walk();
+        assert_eq!(files, vec![
+            dir.join("themes/a.conf.cfy"),
+            dir.join("themes/nord/b.cfy"),
+            dir.join("themes/nord/bar/c.cfy")
+        ]);
+        assert_eq!(dirs, vec![
+            dir.join("themes"),
+            dir.join("themes/nord"),
+            dir.join("themes/nord/bar")
+        ]);
+        assert_eq!(Pattern::new(&dir.join("themes")), Some(pattern.clone()));
+        assert!(pattern.matches(&dir.join("themes/x/y/new.cfy")));
+        assert!(!pattern.matches(&dir.join("themes/new.txt")));
+        assert_eq!(pattern.destination(&files[2], &dir.join("out")),
+            dir.join("out/nord/bar/c"));
+        assert_eq!(pattern.destination(&files[0], &dir.join("out")),
+            dir.join("out/a.conf"));
+
+        let pattern = Pattern::new(&dir.join("themes/n*/*")).unwrap();
+        assert_eq!(pattern.walk().0, vec![dir.join("themes/nord/b.cfy")]);
+        assert_eq!(Pattern::new(&dir.join("themes/a.conf.cfy")), None);
+    }
+}
@@ -2,9 +2,18 @@ mod generator;
 mod watcher;
 mod config;
 mod variables;
+mod template;
+mod expression;
+mod filters;
+mod color;
+mod diff;
+mod glob;
+#[cfg(test)]
+mod testing;
 
 #[macro_use]
 extern crate serde_derive;
+extern crate serde;
 extern crate serde_yaml;
 extern crate inotify;
 extern crate clap;
@@ -15,13 +24,15 @@ extern crate env_logger;
 use std::process::exit;
 use std::path::Path;
 use std::env;
-use std::io::Write;
+use std::fs;
+use std::io;
+use std::io::{Write, IsTerminal};
 use log::Level;
 use env_logger::Color;
 use clap::{App, Arg};
 
 use watcher::{Watcher, Mode};
-use generator::Generator;
+use generator::{Generator, Options, Report, Status};
 use config::{Config, ConfigFiles, PathBinding};
 
 
@@ -32,20 +43,36 @@ fn main() {
         .version("0.2.2")
         .author("Vincent Pasquier")
         .about("Continuously substitute key-value pairs accross multiple configuration files")
+        .arg(Arg::with_name("config_file")
+            .help("Config file with bindings and variables sections (.yaml)")
+            .short("c")
+            .long("config")
+            .value_name("CONFIG_FILE")
+            .takes_value(true)
+            .conflicts_with("bindings_file")
+            .required_unless_all(&["bindings_file", "variables_file"]))
         .arg(Arg::with_name("bindings_file")
             .help("Bindings file (.yaml)")
             .short("b")
             .long("bindings")
             .value_name("BINDINGS_FILE")
             .takes_value(true)
-            .required(true))
+            .requires("variables_file"))
         .arg(Arg::with_name("variables_file")
-            .help("Variables file (.yaml)")
+            .help("Variables file (.yaml), repeatable, later files override earlier ones")
             .short("v")
             .long("variables")
-            .value_name("BINDINGS_FILE")
+            .value_name("VARIABLES_FILE")
             .takes_value(true)
-            .required(true))
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("profile")
+            .help("Switch to the given profile, kept for later runs and running watchers")
+            .short("p")
+            .long("profile")
+            .value_name("PROFILE")
+            .takes_value(true)
+            .conflicts_with_all(&["dry_run", "diff"]))
         .arg(Arg::with_name("watch_files")
             .help("Update to-part of a binding when from-part is modified")
             .short("F")
@@ -58,20 +85,63 @@ fn main() {
             .help("Update internal configuration when the variables file is modified")
             .short("V")
             .long("watch-variables"))
+        .arg(Arg::with_name("watch_config")
+            .help("Update internal configuration when any config file is modified")
+            .short("C")
+            .long("watch-config"))
+        .arg(Arg::with_name("strict")
+            .help("Fail on unresolved keys and malformed expressions instead of leaving them as is")
+            .short("s")
+            .long("strict"))
+        .arg(Arg::with_name("dry_run")
+            .help("Render every binding without writing, exit with 1 if any output file would change")
+            .short("n")
+            .long("dry-run")
+            .conflicts_with_all(&["watch_files", "watch_bindings", "watch_variables",
+                "watch_config"]))
+        .arg(Arg::with_name("diff")
+            .help("Like --dry-run, also printing a diff of every output file which would change")
+            .short("d")
+            .long("diff")
+            .conflicts_with_all(&["watch_files", "watch_bindings", "watch_variables",
+                "watch_config"]))
         .get_matches();
 
     debug!("{:?}", matches);
 
-    let config_files = ConfigFiles {
-        bindings: Path::new(matches.value_of("bindings_file").unwrap()),
-        variables: Path::new(matches.value_of("variables_file").unwrap()),
+    let mut config_files = match matches.value_of("config_file") {
+        Some(config_file) => ConfigFiles::combined(Path::new(config_file)),
+        None => ConfigFiles {
+            bindings: Path::new(matches.value_of("bindings_file").unwrap()),
+            variables: Vec::new(),
+            profile: None
+        }
     };
+    let profile_file = Config::profile_file(config_files.bindings);
+    config_files.profile = profile_file.as_deref();
+    if let Some(variables_files) = matches.values_of("variables_file") {
+        config_files.variables.extend(variables_files.map(Path::new));
+    }
+    let watch_config = matches.is_present("watch_config");
     let mode = Mode {
         files: matches.is_present("watch_files"),
-        bindings: matches.is_present("watch_bindings"),
-        variables: matches.is_present("watch_variables")
+        bindings: watch_config || matches.is_present("watch_bindings"),
+        variables: watch_config || matches.is_present("watch_variables")
+    };
+    let options = Options {
+        strict: matches.is_present("strict")
     };
 
+    if let Some(profile) = matches.value_of("profile") {
+        match Config::set_profile(&config_files, profile) {
+            Ok(()) => info!("switched to profile {}", profile),
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
+            }
+        };
+    }
+
     if !mode.bindings && !mode.variables && !mode.files{
         let config = match Config::new(&config_files) {
             Ok(c) => c,
@@ -81,18 +151,52 @@ fn main() {
             }
         };
 
-        let generator = Generator::new(&config.variables);
+        let generator = Generator::new(
+            &config.variables, &config.literals, options);
+        let generator = match generator {
+            Ok(g) => g,
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
+            }
+        };
+        let dry_run = matches.is_present("dry_run")
+            || matches.is_present("diff");
+        let mut failed = false;
+        let mut changed = false;
         for binding in config.bindings.iter() {
-            match generator.process(binding) {
-                Ok(n) => info!(
-                    "{}: replaced {} key(s)",
-                    binding.from.display(), n),
-                Err(e) => error!("{}", e)
+            let processed = if dry_run {
+                dry_process(&generator, binding, matches.is_present("diff"))
+            }
+            else {
+                generator.process(binding)
+            };
+            match processed {
+                Ok(report) => {
+                    changed |= report.status == Status::Pending;
+                    report.log(binding);
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    failed = true;
+                }
             };
         }
+        if failed || changed {
+            exit(1);
+        }
     }
     else {
-        let mut watcher = match Watcher::new(config_files, mode) {
+        // The profile file may not exist yet, its directory has to for it to
+        // be watched.
+        let profile_dir = profile_file.as_ref().and_then(|p| p.parent());
+        if let Some(dir) = profile_dir {
+            if let Err(e) = fs::create_dir_all(dir) {
+                warn!("Couldn't create {}: {}", dir.display(), e);
+            }
+        }
+
+        let mut watcher = match Watcher::new(config_files, mode, options) {
             Ok(w) => w,
             Err(e) => {
                 error!("{}", e);
@@ -103,6 +207,28 @@ fn main() {
     }
 }
 
+fn dry_process(generator: &Generator, binding: &PathBinding, show_diff: bool)
+    -> Result<Report, String>
+{
+    let (output, mut report) = generator.generate(binding)?;
+    let current = fs::read(&binding.to).ok();
+    let new_name = binding.to.display().to_string();
+
+    let (old_name, current) = match current {
+        Some(ref c) => (new_name.clone(), String::from_utf8_lossy(c)),
+        None => ("/dev/null".to_string(), "".into())
+    };
+    let color = io::stdout().is_terminal();
+
+    match diff::unified(&current, &output, &old_name, &new_name, 3, color) {
+        Some(ref d) if show_diff => print!("{}", d),
+        Some(_) => (),
+        None => report.status = Status::Unchanged
+    };
+
+    Ok(report)
+}
+
 fn log_init() {
     let mut builder = env_logger::Builder::new();
      
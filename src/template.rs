@@ -0,0 +1,725 @@
+use std::fmt;
+
+use variables::Value;
+use expression::{Expression, Arithmetic, Error};
+
+
+#[derive(Debug)]
+pub struct Template {
+    pub nodes: Vec<Node>,
+    pub errors: Vec<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    Text(String),
+    Substitution {
+        expression: Expression,
+        raw: String,
+        position: Position
+    },
+    Conditional {
+        condition: Condition,
+        consequent: Vec<Node>,
+        alternative: Vec<Node>,
+        position: Position
+    },
+    Loop {
+        target: Iterable,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+        position: Position
+    },
+    Include {
+        path: String,
+        position: Position
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Iterable {
+    Variable(String),
+    Prefix(String)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Condition {
+    Defined(Operand),
+    Equal(Operand, Operand),
+    NotEqual(Operand, Operand),
+    Less(Operand, Operand),
+    LessOrEqual(Operand, Operand),
+    Greater(Operand, Operand),
+    GreaterOrEqual(Operand, Operand)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Operand {
+    Variable(String),
+    Literal(Value),
+    Arithmetic(Box<Arithmetic>)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delimiters {
+    pub open: String,
+    pub close: String
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Tag {
+        content: &'a str,
+        raw: &'a str,
+        position: Position
+    }
+}
+
+enum BlockKind {
+    If(Condition),
+    Each(Iterable)
+}
+
+struct OpenBlock {
+    kind: BlockKind,
+    position: Position,
+    consequent: Vec<Node>,
+    alternative: Option<Vec<Node>>
+}
+
+
+impl Template {
+    pub fn parse(input: &str, delimiters: &Delimiters)
+        -> Result<Template, String>
+    {
+        let mut nodes = Vec::new();
+        let mut errors = Vec::new();
+        let mut stack: Vec<OpenBlock> = Vec::new();
+
+        for segment in Template::split(input, delimiters)? {
+            let (content, raw, position) = match segment {
+                Segment::Text(text) => {
+                    Template::current(&mut nodes, &mut stack)
+                        .push(Node::Text(text.to_string()));
+                    continue;
+                },
+                Segment::Tag { content, raw, position } =>
+                    (content.trim(), raw, position)
+            };
+            // Where the character at `offset` in the content stands.
+            let at = |offset: usize| {
+                let inner = &raw[delimiters.open.len()..];
+                let column = position.column
+                    + delimiters.open.chars().count()
+                    + inner.chars()
+                        .take_while(|c| c.is_whitespace())
+                        .count()
+                    + offset;
+                Position { column, ..position }
+            };
+
+            if let Some(block) = content.strip_prefix('#') {
+                let (name, argument) = Template::split_block(block);
+                let start = content[..content.len() - argument.len()]
+                    .chars()
+                    .count();
+                let kind = match name {
+                    "if" => BlockKind::If(match Condition::parse(argument) {
+                        Ok(c) => c,
+                        Err(e) => return Err(format!(
+                            "{}: {}", at(start + e.offset), e.message))
+                    }),
+                    "each" => BlockKind::Each(match Iterable::parse(argument) {
+                        Ok(i) => i,
+                        Err(e) => return Err(format!(
+                            "{}: {}", at(start + e.offset), e.message))
+                    }),
+                    _ => return Err(format!(
+                        "{}: unknown block {}", position, raw))
+                };
+                stack.push(OpenBlock {
+                    kind,
+                    position,
+                    consequent: Vec::new(),
+                    alternative: None
+                });
+            }
+            else if let Some(path) = content.strip_prefix('>') {
+                let path = path.trim();
+                if path.is_empty() {
+                    return Err(format!(
+                        "{}: missing partial in {}", position, raw));
+                }
+                Template::current(&mut nodes, &mut stack).push(Node::Include {
+                    path: path.to_string(),
+                    position
+                });
+            }
+            else if content == "else" {
+                match stack.last_mut() {
+                    Some(ref mut block) if block.alternative.is_none() =>
+                        block.alternative = Some(Vec::new()),
+                    Some(block) => return Err(format!(
+                        "{}: duplicate {} in {} opened at {}",
+                        position, raw, block.kind, block.position)),
+                    None => return Err(format!(
+                        "{}: {} without an open block", position, raw))
+                };
+            }
+            else if let Some(name) = content.strip_prefix('/') {
+                let block = match stack.pop() {
+                    Some(b) => b,
+                    None => return Err(format!(
+                        "{}: {} without an open block", position, raw))
+                };
+                if name.trim() != block.kind.name() {
+                    return Err(format!(
+                        "{}: {} does not close {} opened at {}",
+                        position, raw, block.kind, block.position));
+                }
+                let node = block.close();
+                Template::current(&mut nodes, &mut stack).push(node);
+            }
+            else {
+                // A malformed expression is kept as is in the output, and
+                // reported at the column where it goes wrong.
+                let node = match Expression::parse(content) {
+                    Ok(expression) => Node::Substitution {
+                        expression,
+                        raw: raw.to_string(),
+                        position
+                    },
+                    Err(e) => {
+                        errors.push(format!("{}: {}",
+                            at(e.offset), e.message));
+                        Node::Text(raw.to_string())
+                    }
+                };
+                Template::current(&mut nodes, &mut stack).push(node);
+            }
+        }
+
+        if let Some(block) = stack.pop() {
+            return Err(format!(
+                "{}: unclosed {}", block.position, block.kind));
+        }
+
+        Ok(Template { nodes, errors })
+    }
+
+    fn current<'b>(nodes: &'b mut Vec<Node>, stack: &'b mut [OpenBlock])
+        -> &'b mut Vec<Node>
+    {
+        match stack.last_mut() {
+            Some(block) => match block.alternative {
+                Some(ref mut alternative) => alternative,
+                None => &mut block.consequent
+            },
+            None => nodes
+        }
+    }
+
+    fn split_block(block: &str) -> (&str, &str) {
+        match block.find(char::is_whitespace) {
+            Some(i) => (&block[..i], block[i..].trim()),
+            None => (block, "")
+        }
+    }
+
+    // Cuts the input into text and tags. Tags never span multiple lines, an
+    // opening marker without a matching close on the same line is kept as
+    // text. Block tags standing alone on their line swallow that line.
+    //
+    // An opening marker preceded by its own first character (`$${{`) is
+    // escaped and kept as text without that character, and everything
+    // between `${{#raw}}` and `${{/raw}}` is kept as is.
+    fn split<'a>(input: &'a str, delimiters: &Delimiters)
+        -> Result<Vec<Segment<'a>>, String>
+    {
+        let (open, close) = (&delimiters.open[..], &delimiters.close[..]);
+        let escape = &open[..open.chars().next().map_or(0, char::len_utf8)];
+        let mut segments = Vec::new();
+        let mut text_start = 0;
+        let mut cursor = 0;
+        let mut raw: Option<(Position, &str)> = None;
+
+        while let Some(found) = input[cursor..].find(open) {
+            let start = cursor + found;
+            let inner = start + open.len();
+
+            if raw.is_none() && input[cursor..start].ends_with(escape) {
+                if start - escape.len() > text_start {
+                    segments.push(Segment::Text(
+                        &input[text_start..start - escape.len()]));
+                }
+                text_start = start;
+                cursor = inner;
+                continue;
+            }
+
+            let line_end = input[start..].find('\n')
+                .map(|i| start + i)
+                .unwrap_or(input.len());
+            let stop = match input[inner..line_end.max(inner)].find(close) {
+                Some(i) => inner + i,
+                None => {
+                    cursor = inner;
+                    continue;
+                }
+            };
+            let content = &input[inner..stop];
+            let end_of_tag = stop + close.len();
+            let mut end = end_of_tag;
+            let mut text_end = start;
+
+            if raw.is_some() && content.trim() != "/raw" {
+                cursor = inner;
+                continue;
+            }
+
+            if Template::is_block(content) {
+                let line_start = input[..start].rfind('\n')
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                let before = &input[line_start..start];
+                let after = &input[end..line_end];
+                if before.trim().is_empty() && after.trim().is_empty() {
+                    text_end = line_start.max(text_start);
+                    end = (line_end + 1).min(input.len());
+                }
+            }
+
+            if text_end > text_start {
+                segments.push(Segment::Text(&input[text_start..text_end]));
+            }
+            let position = Position::of(input, start);
+            let tag = &input[start..end_of_tag];
+            text_start = end;
+            cursor = end;
+
+            if raw.is_some() {
+                raw = None;
+            }
+            else if content.trim() == "#raw" {
+                raw = Some((position, tag));
+            }
+            else {
+                segments.push(Segment::Tag { content, raw: tag, position });
+            }
+        }
+
+        if let Some((position, tag)) = raw {
+            return Err(format!("{}: unclosed {}", position, tag));
+        }
+        if text_start < input.len() {
+            segments.push(Segment::Text(&input[text_start..]));
+        }
+
+        Ok(segments)
+    }
+
+    fn is_block(content: &str) -> bool {
+        let content = content.trim();
+        content.starts_with('#') || content.starts_with('/')
+            || content.starts_with('>') || content == "else"
+    }
+}
+
+impl Delimiters {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.open.is_empty() || self.close.is_empty() {
+            return Err("delimiters cannot be empty".to_string());
+        }
+        if self.open == self.close {
+            return Err(format!(
+                "opening and closing delimiters are both '{}'", self.open));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Delimiters {
+    fn default() -> Delimiters {
+        Delimiters {
+            open: "${{".to_string(),
+            close: "}}".to_string()
+        }
+    }
+}
+
+impl Iterable {
+    fn parse(input: &str) -> Result<Iterable, Error> {
+        if input.is_empty() || input.contains(char::is_whitespace) {
+            return Err(Error {
+                offset: 0,
+                message: format!("malformed loop target '{}'", input)
+            });
+        }
+
+        match input.strip_suffix(".*") {
+            Some(prefix) => Ok(Iterable::Prefix(format!("{}.", prefix))),
+            None => Ok(Iterable::Variable(input.to_string()))
+        }
+    }
+}
+
+impl Condition {
+    // Each side of the comparison is a key, a literal or a calculation.
+    // Errors are at an offset from the start of the condition.
+    fn parse(input: &str) -> Result<Condition, Error> {
+        let (left, operator, right) = match Condition::split(input) {
+            Some((i, operator)) =>
+                (&input[..i], Some(operator), &input[i + operator.len()..]),
+            None => (input, None, "")
+        };
+        let operand = |side: &str, start: usize|
+            Expression::parse_operand(side).map_err(|e| Error {
+                offset: start + e.offset,
+                message: format!(
+                    "{} in condition '{}'", e.message, input.trim())
+            });
+        let a = operand(left, 0)?;
+        let b = match operator {
+            Some(_) => operand(right,
+                input[..input.len() - right.len()].chars().count())?,
+            None => return Ok(Condition::Defined(a))
+        };
+
+        Ok(match operator {
+            Some("==") => Condition::Equal(a, b),
+            Some("!=") => Condition::NotEqual(a, b),
+            Some("<") => Condition::Less(a, b),
+            Some("<=") => Condition::LessOrEqual(a, b),
+            Some(">") => Condition::Greater(a, b),
+            _ => Condition::GreaterOrEqual(a, b)
+        })
+    }
+
+    // Finds the first comparison operator outside of quotes.
+    fn split(input: &str) -> Option<(usize, &'static str)> {
+        let mut quote = None;
+        for (i, c) in input.char_indices() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => (),
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None => {
+                    let found = ["==", "!=", "<=", ">=", "<", ">"].iter()
+                        .find(|o| input[i..].starts_with(*o));
+                    if let Some(o) = found {
+                        return Some((i, o));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Position {
+    fn of(input: &str, offset: usize) -> Position {
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Position {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl BlockKind {
+    fn name(&self) -> &'static str {
+        match *self {
+            BlockKind::If(_) => "if",
+            BlockKind::Each(_) => "each"
+        }
+    }
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} block", self.name())
+    }
+}
+
+impl OpenBlock {
+    fn close(self) -> Node {
+        match self.kind {
+            BlockKind::If(condition) => Node::Conditional {
+                condition,
+                consequent: self.consequent,
+                alternative: self.alternative.unwrap_or_default(),
+                position: self.position
+            },
+            BlockKind::Each(target) => Node::Loop {
+                target,
+                body: self.consequent,
+                empty: self.alternative.unwrap_or_default(),
+                position: self.position
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Template, String> {
+        Template::parse(input, &Delimiters::default())
+    }
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_string())
+    }
+
+    fn variable(s: &str) -> Operand {
+        Operand::Variable(s.to_string())
+    }
+
+    fn at(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    fn substitution(key: &str, raw: &str, position: Position) -> Node {
+        Node::Substitution {
+            expression: Expression {
+                alternatives: vec![variable(key)],
+                filters: vec![]
+            },
+            raw: raw.to_string(),
+            position
+        }
+    }
+
+    #[test]
+    fn test_substitution() {
+        let template = parse("a ${{ key }} b ${{ c\n}}").unwrap();
+
+        assert_eq!(template.nodes, vec![
+            text("a "),
+            substitution("key", "${{ key }}", at(1, 3)),
+            text(" b ${{ c\n}}")
+        ]);
+    }
+
+    #[test]
+    fn test_delimiters() {
+        let delimiters = Delimiters {
+            open: "<%".to_string(),
+            close: "%>".to_string()
+        };
+        let template = Template::parse(
+            "${{ a }} <% a %>\n<%#if a%>\nx\n<%/if%>\n", &delimiters).unwrap();
+
+        assert_eq!(template.nodes, vec![
+            text("${{ a }} "),
+            substitution("a", "<% a %>", at(1, 10)),
+            text("\n"),
+            Node::Conditional {
+                condition: Condition::Defined(variable("a")),
+                consequent: vec![text("x\n")],
+                alternative: vec![],
+                position: at(2, 1)
+            }
+        ]);
+
+        assert!(Delimiters::default().validate().is_ok());
+        assert!(Delimiters { open: "".to_string(), close: "}".to_string() }
+            .validate().is_err());
+        assert!(Delimiters { open: "%".to_string(), close: "%".to_string() }
+            .validate().is_err());
+    }
+
+    #[test]
+    fn test_escapes() {
+        let template = parse("$${{a}} $$${{ a }}").unwrap();
+        assert_eq!(template.nodes, vec![
+            text("${{a}} $"),
+            text("${{ a }}")
+        ]);
+
+        let template = parse(
+            "${{a}}\n  ${{#raw}}\n{{ a }} ${{a}} ${{#if}}\n${{/raw}}\n$${{").unwrap();
+        assert_eq!(template.nodes, vec![
+            substitution("a", "${{a}}", at(1, 1)),
+            text("\n"),
+            text("{{ a }} ${{a}} ${{#if}}\n"),
+            text("${{")
+        ]);
+
+        let unclosed = parse("a\n${{#raw}}\n${{a}}").unwrap_err();
+        assert!(unclosed.starts_with("line 2, column 1:"), "{}", unclosed);
+        assert!(parse("${{/raw}}").is_err());
+    }
+
+    #[test]
+    fn test_malformed_expression() {
+        let template = parse("${{a | nope}} ${{b c}}").unwrap();
+
+        assert_eq!(template.nodes, vec![
+            text("${{a | nope}}"),
+            text(" "),
+            text("${{b c}}")
+        ]);
+        assert_eq!(template.errors, vec![
+            "line 1, column 8: unknown filter 'nope'",
+            "line 1, column 20: expected '|', found 'c'"
+        ]);
+    }
+
+    #[test]
+    fn test_nested_conditionals() {
+        let input = "\
+            start\n\
+            ${{#if a}}\n\
+            \x20 ${{#if b == 'x y'}}\n\
+            both\n\
+            \x20 ${{else}}\n\
+            a only\n\
+            \x20 ${{/if}}\n\
+            ${{else}}\n\
+            none\n\
+            ${{/if}}\n\
+            end\n";
+        let template = parse(input).unwrap();
+
+        assert_eq!(template.nodes, vec![
+            text("start\n"),
+            Node::Conditional {
+                condition: Condition::Defined(variable("a")),
+                consequent: vec![
+                    Node::Conditional {
+                        condition: Condition::Equal(
+                            variable("b"),
+                            Operand::Literal(Value::String("x y".to_string()))),
+                        consequent: vec![text("both\n")],
+                        alternative: vec![text("a only\n")],
+                        position: at(3, 3)
+                    }
+                ],
+                alternative: vec![text("none\n")],
+                position: at(2, 1)
+            },
+            text("end\n")
+        ]);
+    }
+
+    #[test]
+    fn test_inline_conditional() {
+        let template = parse(
+            "x ${{#if a != b}}y${{/if}} z\n").unwrap();
+
+        assert_eq!(template.nodes, vec![
+            text("x "),
+            Node::Conditional {
+                condition: Condition::NotEqual(variable("a"), variable("b")),
+                consequent: vec![text("y")],
+                alternative: vec![],
+                position: at(1, 3)
+            },
+            text(" z\n")
+        ]);
+    }
+
+    #[test]
+    fn test_loops() {
+        let input = "\
+            ${{#each color.*}}\n\
+            ${{#each @value}}${{@key}}${{/each}}\n\
+            ${{/each}}\n\
+            ${{#each fonts}}\n\
+            ${{else}}\n\
+            none\n\
+            ${{/each}}\n";
+        let template = parse(input).unwrap();
+
+        assert_eq!(template.nodes, vec![
+            Node::Loop {
+                target: Iterable::Prefix("color.".to_string()),
+                body: vec![
+                    Node::Loop {
+                        target: Iterable::Variable("@value".to_string()),
+                        body: vec![
+                            substitution("@key", "${{@key}}", at(2, 18))
+                        ],
+                        empty: vec![],
+                        position: at(2, 1)
+                    },
+                    text("\n")
+                ],
+                empty: vec![],
+                position: at(1, 1)
+            },
+            Node::Loop {
+                target: Iterable::Variable("fonts".to_string()),
+                body: vec![],
+                empty: vec![text("none\n")],
+                position: at(4, 1)
+            }
+        ]);
+
+        assert!(parse("${{#each}}${{/each}}").is_err());
+        assert!(parse("${{#each a}}${{/if}}").is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let template = parse(
+            "a\n  ${{> partials/keys.cfy }}\n${{#if a}}${{>b.cfy}}${{/if}}\n")
+            .unwrap();
+
+        assert_eq!(template.nodes, vec![
+            text("a\n"),
+            Node::Include {
+                path: "partials/keys.cfy".to_string(),
+                position: at(2, 3)
+            },
+            Node::Conditional {
+                condition: Condition::Defined(variable("a")),
+                consequent: vec![Node::Include {
+                    path: "b.cfy".to_string(),
+                    position: at(3, 11)
+                }],
+                alternative: vec![],
+                position: at(3, 1)
+            },
+            text("\n")
+        ]);
+
+        assert!(parse("${{>}}").is_err());
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        let unclosed = parse("a\n  ${{#if a}}\n").unwrap_err();
+        assert!(unclosed.starts_with("line 2, column 3:"), "{}", unclosed);
+
+        assert!(parse("${{/if}}").is_err());
+        assert!(parse("${{else}}").is_err());
+        assert!(parse("${{#if a}}${{else}}${{else}}${{/if}}").is_err());
+        assert!(parse("${{#if a b}}${{/if}}").is_err());
+        assert!(parse("${{#if a ==}}${{/if}}").is_err());
+        assert!(parse("${{#if a < b > c}}${{/if}}").is_err());
+        assert!(parse("${{#unknown}}").is_err());
+
+        assert_eq!(parse("${{#if a < b > c}}${{/if}}").unwrap_err(),
+            "line 1, column 14: unexpected '>' in condition 'a < b > c'");
+        assert_eq!(parse("é ${{ #if 'é' == b c }}${{/if}}").unwrap_err(),
+            "line 1, column 20: unexpected 'c' in condition ''é' == b c'");
+        assert_eq!(parse("${{#each a b}}${{/each}}").unwrap_err(),
+            "line 1, column 10: malformed loop target 'a b'");
+    }
+}
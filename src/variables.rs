@@ -1,11 +1,32 @@
 use std::ops::Deref;
+use std::borrow::Cow;
 use std::cell::Cell;
-use std::collections::HashMap;
-use std::env::{vars};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, BTreeMap};
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde_yaml;
+
+// Variable values use the default delimiters to reference each other.
+const OPEN: &str = "${{";
+const CLOSE: &str = "}}";
 
 #[derive(Debug)]
 pub struct Variables {
-    map: HashMap<String, String>
+    map: HashMap<String, Value>,
+    problems: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>)
 }
 
 #[derive(Copy, Clone)]
@@ -19,19 +40,24 @@ struct DisjointSet {
 }
 
 impl Deref for Variables {
-    type Target = HashMap<String, String>;
+    type Target = HashMap<String, Value>;
 
-    fn deref(&self) -> &HashMap<String, String> {
+    fn deref(&self) -> &HashMap<String, Value> {
         &self.map
     }
 }
 
 impl Variables {
-    pub fn new(map: &HashMap<String, String>) -> Variables {
-        let original = map;
-        let env = Variables::env_map();
-        let max_len = original.len() + env.len();
+    // The keys of `literals`, such as environment variables, hold values
+    // taken as they are: they may be aliased or interpolated by others, but
+    // are never resolved themselves.
+    pub fn new(map: &HashMap<String, Value>, literals: &HashSet<String>)
+        -> Variables
+    {
+        let original = Variables::flatten(map);
+        let max_len = original.len();
         let mut map = HashMap::with_capacity(max_len);
+        let mut roots = HashMap::with_capacity(original.len());
         let mut recto = HashMap::with_capacity(max_len);
         let mut verso = Vec::with_capacity(max_len);
         let mut set = DisjointSet::new(max_len);
@@ -41,15 +67,14 @@ impl Variables {
             verso.push(&key[..]);
         }
 
-        for key in env.keys() {
-            recto.insert(&key[..], verso.len());
-            verso.push(&key[..]);
-        }
-
         for (key, value) in original.iter() {
-            if value.starts_with("@") {
+            let value = match *value {
+                Value::String(ref s) if !literals.contains(key) => s,
+                _ => continue
+            };
+            if let Some(target) = value.strip_prefix('@') {
                 let i = recto.get(&key[..]).unwrap();
-                if let Some(j) = recto.get(&value[1..]) {
+                if let Some(j) = recto.get(target) {
                     set.merge(*j, *i);
                 }
             }
@@ -59,28 +84,374 @@ impl Variables {
             let i = recto.get(&key[..]).unwrap();
             let j = set.root(*i);
             let deref_key = verso[j];
-            let value = original.get(deref_key).unwrap_or_else(
-                || env.get(deref_key).unwrap()
-            );
-            map.insert(key.clone(), value.clone());
+            map.insert(key.clone(), original[deref_key].clone());
+            roots.insert(&key[..], deref_key);
         }
 
-        for (key, value) in env.iter() {
-            map.insert(key.clone(), value.clone());
+        // A value still starting with `@` once dereferenced is either part of
+        // a cycle or aliases a key which doesn't exist. Both are reported
+        // once, from the root of their set.
+        let mut problems = Vec::new();
+        for (key, root) in roots.iter() {
+            if key != root || literals.contains(*key) {
+                continue;
+            }
+            let target = match original.get(*root) {
+                Some(Value::String(s)) => match s.strip_prefix('@') {
+                    Some(target) => target,
+                    None => continue
+                },
+                _ => continue
+            };
+            if recto.contains_key(target) {
+                problems.push(Variables::cycle(root, &original));
+            }
+            else {
+                problems.push(format!(
+                    "Variable {} references undefined key '{}'", root, target));
+            }
         }
 
+        // Aliases of a literal are just as literal.
+        let literals: HashSet<String> = roots.iter()
+            .filter(|&(_, root)| literals.contains(*root))
+            .map(|(key, _)| key.to_string())
+            .collect();
+        Variables::interpolate(&mut map, &literals, &mut problems);
+        problems.sort();
+
+        // Structured values get the dereferenced values of their fields.
+        let restored: Vec<(String, Value)> = map.iter()
+            .filter(|&(_, value)| value.as_text().is_none())
+            .map(|(key, value)| (key.clone(),
+                Variables::restore(roots[&key[..]], value, &map)))
+            .collect();
+        map.extend(restored);
+
         debug!("original variables: {:?}", original);
         debug!("dereferenced variables: {:?}", map);
 
-        Variables { map }
+        Variables { map, problems }
     }
 
-    fn env_map() -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        for (key, value) in vars() {
-            map.insert(key, value);
+    // Cycles, dangling references and other inconsistencies found while
+    // resolving the variables.
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    // Follows the `@` aliases from `key` until one of them comes back, and
+    // describes the loop starting from its smallest key.
+    fn cycle(key: &str, original: &HashMap<String, Value>) -> String {
+        let mut chain: Vec<&str> = Vec::new();
+        let mut key = key;
+        while !chain.contains(&key) {
+            chain.push(key);
+            key = match original.get(key) {
+                Some(Value::String(s)) => match s.strip_prefix('@') {
+                    Some(target) => target,
+                    None => break
+                },
+                _ => break
+            };
+        }
+        let start = chain.iter().position(|k| *k == key).unwrap_or(0);
+        let mut cycle = chain.split_off(start);
+        let smallest = (0..cycle.len())
+            .min_by_key(|&i| cycle[i])
+            .unwrap_or(0);
+        cycle.rotate_left(smallest);
+        if let Some(first) = cycle.first().cloned() {
+            cycle.push(first);
+        }
+        format!("Variables {} reference each other", cycle.join(" -> "))
+    }
+
+    // Adds a dotted key for every field of nested maps and lists, unless
+    // that key is also given as is.
+    fn flatten(map: &HashMap<String, Value>) -> HashMap<String, Value> {
+        fn visit(prefix: &str, value: &Value,
+            flat: &mut HashMap<String, Value>)
+        {
+            let fields: Vec<(String, &Value)> = match *value {
+                Value::Map(ref map) => map.iter()
+                    .map(|(key, value)| (key.clone(), value))
+                    .collect(),
+                Value::List(ref list) => list.iter()
+                    .enumerate()
+                    .map(|(i, value)| (i.to_string(), value))
+                    .collect(),
+                _ => return
+            };
+            for (key, value) in fields {
+                let key = format!("{}.{}", prefix, key);
+                visit(&key, value, flat);
+                flat.entry(key).or_insert_with(|| value.clone());
+            }
+        }
+
+        let mut flat = map.clone();
+        for (key, value) in map.iter() {
+            visit(key, value, &mut flat);
+        }
+        flat
+    }
+
+    fn restore(prefix: &str, value: &Value, map: &HashMap<String, Value>)
+        -> Value
+    {
+        match *value {
+            Value::List(ref list) => Value::List(list.iter()
+                .enumerate()
+                .map(|(i, value)| Variables::restore(
+                    &format!("{}.{}", prefix, i), value, map))
+                .collect()),
+            Value::Map(ref fields) => Value::Map(fields.iter()
+                .map(|(key, value)| (key.clone(), Variables::restore(
+                    &format!("{}.{}", prefix, key), value, map)))
+                .collect()),
+            _ => map.get(prefix)
+                .cloned()
+                .unwrap_or_else(|| value.clone())
+        }
+    }
+
+    // Replaces every `${{key}}` inside string values by the value of that
+    // key, itself interpolated first. References which are undefined, not
+    // scalars or part of a cycle are left as is.
+    fn interpolate(map: &mut HashMap<String, Value>,
+        literals: &HashSet<String>, problems: &mut Vec<String>)
+    {
+        let keys: Vec<String> = map.iter()
+            .filter(|&(key, value)| match *value {
+                Value::String(ref s) => s.contains(OPEN)
+                    && !literals.contains(key),
+                _ => false
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        // Literals are already as resolved as they get.
+        let mut resolved: HashMap<String, String> = literals.iter()
+            .filter_map(|key| map.get(key)
+                .and_then(Value::as_text)
+                .map(|text| (key.clone(), text.into_owned())))
+            .collect();
+        for key in keys.iter() {
+            let mut stack = Vec::new();
+            Variables::expand(key, map, &mut resolved, &mut stack, problems);
+        }
+        for (key, value) in resolved {
+            map.insert(key, Value::String(value));
+        }
+    }
+
+    fn expand(key: &str, map: &HashMap<String, Value>,
+        resolved: &mut HashMap<String, String>,
+        stack: &mut Vec<String>, problems: &mut Vec<String>)
+        -> Option<String>
+    {
+        if let Some(value) = resolved.get(key) {
+            return Some(value.clone());
+        }
+        let value = map.get(key)?;
+        let mut rest = match *value {
+            Value::String(ref s) if s.contains(OPEN) => &s[..],
+            _ => return value.as_text().map(Cow::into_owned)
+        };
+
+        stack.push(key.to_string());
+        let mut output = String::with_capacity(rest.len());
+        while let Some(start) = rest.find(OPEN) {
+            let (before, after) = rest.split_at(start);
+            if let Some(before) = before.strip_suffix('$') {
+                output.push_str(before);
+                output.push_str(OPEN);
+                rest = &after[OPEN.len()..];
+                continue;
+            }
+            output.push_str(before);
+            let end = match after.find(CLOSE) {
+                Some(end) => end + CLOSE.len(),
+                None => {
+                    rest = after;
+                    break;
+                }
+            };
+            let reference = after[OPEN.len()..end - CLOSE.len()].trim();
+            rest = &after[end..];
+
+            if let Some(i) = stack.iter().position(|k| k == reference) {
+                let mut cycle = stack[i..].to_vec();
+                cycle.push(reference.to_string());
+                problems.push(format!("Variables {} reference each other",
+                    cycle.join(" -> ")));
+            }
+            else if let Some(text) = Variables::expand(
+                reference, map, resolved, stack, problems)
+            {
+                output.push_str(&text);
+                continue;
+            }
+            else {
+                problems.push(format!("Variable {} references '{}', which \
+                    is undefined or not a scalar", key, reference));
+            }
+            output.push_str(&after[..end]);
+        }
+        output.push_str(rest);
+        stack.pop();
+
+        resolved.insert(key.to_string(), output.clone());
+        Some(output)
+    }
+}
+
+// Values are read through YAML ones, so that keys of nested maps may be any
+// scalar rather than only strings.
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Value, D::Error>
+        where D: Deserializer<'de>
+    {
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        Value::from_yaml(value, "").map_err(de::Error::custom)
+    }
+}
+
+impl Value {
+    // `key` is the dotted path of the value, to tell where errors are.
+    pub fn from_yaml(value: serde_yaml::Value, key: &str)
+        -> Result<Value, String>
+    {
+        let child = |name: &str| if key.is_empty() {
+            name.to_string()
+        }
+        else {
+            format!("{}.{}", key, name)
+        };
+        Ok(match value {
+            serde_yaml::Value::Null => Value::Null,
+            serde_yaml::Value::Bool(b) => Value::Bool(b),
+            serde_yaml::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN))
+            },
+            serde_yaml::Value::String(s) => Value::String(s),
+            serde_yaml::Value::Sequence(list) => Value::List(list.into_iter()
+                .enumerate()
+                .map(|(i, value)|
+                    Value::from_yaml(value, &child(&i.to_string())))
+                .collect::<Result<Vec<Value>, String>>()?),
+            serde_yaml::Value::Mapping(map) => {
+                let mut fields = BTreeMap::new();
+                for (name, value) in map {
+                    let name = Value::key(name, key)?;
+                    let value = Value::from_yaml(value, &child(&name))?;
+                    fields.insert(name, value);
+                }
+                Value::Map(fields)
+            }
+        })
+    }
+
+    // Booleans and numbers used as keys are written out.
+    fn key(name: serde_yaml::Value, key: &str) -> Result<String, String> {
+        let found = match name {
+            serde_yaml::Value::String(s) => return Ok(s),
+            serde_yaml::Value::Bool(b) => return Ok(b.to_string()),
+            serde_yaml::Value::Number(n) => return Ok(n.to_string()),
+            serde_yaml::Value::Null => "null",
+            serde_yaml::Value::Sequence(_) => "a list",
+            serde_yaml::Value::Mapping(_) => "a map"
+        };
+        let place = if key.is_empty() {
+            String::new()
+        }
+        else {
+            format!(" in '{}'", key)
+        };
+        Err(format!("invalid key{}: expected a string, a number or a \
+            boolean, found {}", place, found))
+    }
+
+    // Scalars as they are written out: `null` is empty, and floats have as
+    // few digits as needed.
+    pub fn as_text(&self) -> Option<Cow<'_, str>> {
+        match *self {
+            Value::Null => Some(Cow::Borrowed("")),
+            Value::Bool(true) => Some(Cow::Borrowed("true")),
+            Value::Bool(false) => Some(Cow::Borrowed("false")),
+            Value::Integer(i) => Some(Cow::Owned(i.to_string())),
+            Value::Float(f) => Some(Cow::Owned(f.to_string())),
+            Value::String(ref s) => Some(Cow::Borrowed(s)),
+            Value::List(_) | Value::Map(_) => None
+        }
+    }
+
+    // Numbers, and strings which read as numbers.
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            Value::Integer(i) => Some(i as f64),
+            Value::Float(f) => Some(f),
+            Value::String(ref s) => s.trim().parse::<f64>().ok()
+                .filter(|f| f.is_finite()),
+            _ => None
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(*self, Value::Integer(_) | Value::Float(_))
+    }
+
+    // `null`, `false`, zero and empty values are false.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Bool(b) => b,
+            Value::Integer(i) => i != 0,
+            Value::Float(f) => f != 0.0,
+            Value::String(ref s) => !s.is_empty(),
+            Value::List(ref list) => !list.is_empty(),
+            Value::Map(ref map) => !map.is_empty()
+        }
+    }
+
+    // Reads a bare word of a template as a number, a boolean or `null`.
+    pub fn parse_literal(word: &str) -> Option<Value> {
+        match word {
+            "true" => return Some(Value::Bool(true)),
+            "false" => return Some(Value::Bool(false)),
+            "null" => return Some(Value::Null),
+            _ => ()
+        };
+        if let Ok(i) = word.parse::<i64>() {
+            return Some(Value::Integer(i));
+        }
+        match word.parse::<f64>() {
+            Ok(f) if f.is_finite() => Some(Value::Float(f)),
+            _ => None
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Map(ref map) => map.get(key),
+            Value::List(ref list) => match key.parse::<usize>() {
+                Ok(i) => list.get(i),
+                Err(_) => None
+            },
+            _ => None
+        }
+    }
+
+    // Orders keys so that numeric ones come first in numeric order, which
+    // keeps `color.2` before `color.10`.
+    pub fn compare_keys(a: &str, b: &str) -> Ordering {
+        match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a.cmp(b)
         }
-        map
     }
 }
 
@@ -127,8 +498,8 @@ impl DisjointSet {
             return
         }
 
-        match self.elements[a].get_mut() {
-            &mut Node::Root(mut _size_a) => {
+        match *self.elements[a].get_mut() {
+            Node::Root(mut _size_a) => {
                 match self.elements[b].get() {
                     Node::Root(_size_b) => {
                         _size_a += _size_b;
@@ -138,7 +509,7 @@ impl DisjointSet {
                         panic!("Unexepected child element: Child({})", r)
                 }
             },
-            &mut Node::Child(r) => 
+            Node::Child(r) =>
                 panic!("Unexepected child element: Child({})", r)
         };
     }
@@ -148,21 +519,44 @@ impl DisjointSet {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_scalar_keys() {
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(
+            "{color: {0: '#000000', 1: '#ffffff'}, \
+            flags: {true: 1, false: 0, on: 2}}").unwrap();
+        let variables = Variables::new(&map, &HashSet::new());
+        let string = |s: &str| Value::String(s.to_string());
+
+        assert_eq!(variables["color.0"], string("#000000"));
+        assert_eq!(variables["color"].get("1"), Some(&string("#ffffff")));
+        assert_eq!(variables["flags.true"], Value::Integer(1));
+        assert_eq!(variables["flags.false"], Value::Integer(0));
+        assert_eq!(variables["flags.on"], Value::Integer(2));
+    }
+
     #[test]
     fn test_variables() {
         let mut map = HashMap::new();
-        let abc = "ABC".to_string();
+        let abc = Value::String("ABC".to_string());
+        let string = |s: &str| Value::String(s.to_string());
 
-        map.insert("ab".to_string(), "@abc".to_string());
-        map.insert("-a".to_string(), "@".to_string());
-        map.insert("".to_string(), "@a".to_string());
-        map.insert("a".to_string(), "@ab".to_string());
+        map.insert("ab".to_string(), string("@abc"));
+        map.insert("-a".to_string(), string("@"));
+        map.insert("".to_string(), string("@a"));
+        map.insert("a".to_string(), string("@ab"));
         map.insert("abc".to_string(), abc.clone());
 
-        map.insert("loop0".to_string(), "@loop1".to_string());
-        map.insert("loop1".to_string(), "@loop0".to_string());
+        map.insert("loop0".to_string(), string("@loop1"));
+        map.insert("loop1".to_string(), string("@loop0"));
+        map.insert("loop2".to_string(), string("@loop1"));
+        map.insert("dangling".to_string(), string("@missing"));
+        map.insert("dangling_alias".to_string(), string("@dangling"));
 
-        let variables = Variables::new(&map);
+        let list = Value::List(vec![string("x"), string("y")]);
+        map.insert("list".to_string(), list.clone());
+        map.insert("alias".to_string(), string("@list"));
+
+        let variables = Variables::new(&map, &HashSet::new());
 
         assert_eq!(variables.get("-a").unwrap(), &abc);
         assert_eq!(variables.get("").unwrap(), &abc);
@@ -171,6 +565,154 @@ mod test {
         assert_eq!(variables.get("abc").unwrap(), &abc);
 
         assert_eq!(variables.get("loop0").unwrap(), variables.get("loop1").unwrap());
+        assert_eq!(variables.get("dangling").unwrap(), &string("@missing"));
+        assert_eq!(variables.get("dangling_alias").unwrap(),
+            &string("@missing"));
+
+        assert_eq!(variables.get("alias").unwrap(), &list);
+
+        assert_eq!(variables.problems(), &[
+            "Variable dangling references undefined key 'missing'",
+            "Variables loop0 -> loop1 -> loop0 reference each other"
+        ]);
+    }
+
+    #[test]
+    fn test_nested() {
+        let string = |s: &str| Value::String(s.to_string());
+        let yaml = "
+            color:
+                primary: '#abcdef'
+                secondary: '@color.bright.0'
+                bright: ['#ffffff', '@color.primary']
+            fonts: [Hack, Fira]
+            fonts.1: Iosevka
+            accent: '@color.bright'
+            main_font: '@fonts.0'
+        ";
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(yaml).unwrap();
+        let variables = Variables::new(&map, &HashSet::new());
+
+        assert_eq!(variables["color.primary"], string("#abcdef"));
+        assert_eq!(variables["color.secondary"], string("#ffffff"));
+        assert_eq!(variables["color.bright.1"], string("#abcdef"));
+        assert_eq!(variables["fonts.0"], string("Hack"));
+        assert_eq!(variables["fonts.1"], string("Iosevka"));
+        assert_eq!(variables["main_font"], string("Hack"));
+
+        let bright = Value::List(vec![string("#ffffff"), string("#abcdef")]);
+        assert_eq!(variables["color.bright"], bright);
+        assert_eq!(variables["accent"], bright);
+        assert_eq!(variables["color"].get("secondary"),
+            Some(&string("#ffffff")));
+        assert_eq!(variables["fonts"],
+            Value::List(vec![string("Hack"), string("Iosevka")]));
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let string = |s: &str| Value::String(s.to_string());
+        let yaml = "
+            font:
+                family: Hack
+                size: 11
+                full: '${{font.family}} ${{ font.size }}'
+            bar: '@font.full'
+            title: '[${{bar}}] $${{kept}} ${{unclosed'
+            wallpaper: '~/walls/${{theme}}.png'
+            theme: '${{theme_name}}'
+            theme_name: nord
+            missing: 'a ${{nope}} b'
+            list: [1, 2]
+            structured: 'x${{list}}'
+            loop0: '0${{loop1}}'
+            loop1: '1${{loop0}}'
+        ";
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(yaml).unwrap();
+        let variables = Variables::new(&map, &HashSet::new());
+
+        assert_eq!(variables["font.full"], string("Hack 11"));
+        assert_eq!(variables["bar"], string("Hack 11"));
+        assert_eq!(variables["font"].get("full"), Some(&string("Hack 11")));
+        assert_eq!(variables["title"],
+            string("[Hack 11] ${{kept}} ${{unclosed"));
+        assert_eq!(variables["wallpaper"], string("~/walls/nord.png"));
+        assert_eq!(variables["missing"], string("a ${{nope}} b"));
+        assert_eq!(variables["structured"], string("x${{list}}"));
+
+        let loops = [&variables["loop0"], &variables["loop1"]];
+        assert!(loops.contains(&&string("01${{loop0}}"))
+            || loops.contains(&&string("10${{loop1}}")));
+
+        let problems = variables.problems();
+        assert_eq!(problems.len(), 3);
+        assert!(problems.contains(&"Variable missing references 'nope', \
+            which is undefined or not a scalar".to_string()));
+        assert!(problems.contains(&"Variable structured references 'list', \
+            which is undefined or not a scalar".to_string()));
+        assert!(problems.iter().any(|p| p.contains("reference each other")));
+    }
+
+    #[test]
+    fn test_literals() {
+        let yaml = "
+            env: {A: '${{font}}', B: '@font', C: '@env.A'}
+            font: Hack
+            a: '@env.A'
+            b: 'x ${{env.B}}'
+        ";
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(yaml).unwrap();
+        let literals = ["env.A", "env.B", "env.C"].iter()
+            .map(|key| key.to_string())
+            .collect();
+        let variables = Variables::new(&map, &literals);
+        let string = |s: &str| Value::String(s.to_string());
+
+        assert_eq!(variables["env.A"], string("${{font}}"));
+        assert_eq!(variables["env.B"], string("@font"));
+        assert_eq!(variables["env.C"], string("@env.A"));
+        assert_eq!(variables["env"].get("B"), Some(&string("@font")));
+        assert_eq!(variables["a"], string("${{font}}"));
+        assert_eq!(variables["b"], string("x @font"));
+        assert!(variables.problems().is_empty());
+    }
+
+    #[test]
+    fn test_scalars() {
+        let yaml = "{size: 11, scale: 1.5, ratio: 2.0, on: true, off: ~, \
+            name: '11'}";
+        let map: HashMap<String, Value> = ::serde_yaml::from_str(yaml).unwrap();
+        let variables = Variables::new(&map, &HashSet::new());
+        let text = |key: &str| variables[key].as_text().unwrap().into_owned();
+
+        assert_eq!(variables["size"], Value::Integer(11));
+        assert_eq!(variables["scale"], Value::Float(1.5));
+        assert_eq!(variables["on"], Value::Bool(true));
+        assert_eq!(variables["off"], Value::Null);
+        assert_eq!(variables["name"], Value::String("11".to_string()));
+
+        assert_eq!(text("size"), "11");
+        assert_eq!(text("scale"), "1.5");
+        assert_eq!(text("ratio"), "2");
+        assert_eq!(text("on"), "true");
+        assert_eq!(text("off"), "");
+
+        assert_eq!(variables["name"].as_number(), Some(11.0));
+        assert!(!variables["off"].is_truthy());
+        assert!(!Value::Integer(0).is_truthy());
+        assert_eq!(Value::parse_literal("-3"), Some(Value::Integer(-3)));
+        assert_eq!(Value::parse_literal("0.5"), Some(Value::Float(0.5)));
+        assert_eq!(Value::parse_literal("null"), Some(Value::Null));
+        assert_eq!(Value::parse_literal("inf"), None);
+        assert_eq!(Value::parse_literal("a"), None);
+    }
+
+    #[test]
+    fn test_compare_keys() {
+        let mut keys = vec!["primary", "10", "2", "0", "bright"];
+        keys.sort_by(|a, b| Value::compare_keys(a, b));
+
+        assert_eq!(keys, vec!["0", "2", "10", "bright", "primary"]);
     }
 
     #[test]
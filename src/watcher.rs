@@ -1,46 +1,65 @@
 use std::path::{PathBuf, Path};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fmt;
+use std::fs;
 use std::io;
 
-use inotify::{Event, WatchMask, WatchDescriptor, Inotify};
+use inotify::{Event, EventMask, WatchMask, WatchDescriptor, Inotify};
 use ::PathBinding;
-use generator::Generator;
+use generator::{Generator, Options};
 use config::{Config, ConfigFiles};
+use glob::Pattern;
 
 
 pub struct Watcher<'a> {
     config_files: ConfigFiles<'a>,
     inotify: Inotify,
     generator: Option<Generator>,
+    elements: Vec<WatchedElement>,
     watches: Watches,
-    mode: Mode
+    mode: Mode,
+    options: Options
 }
 
-type Watches = HashMap<ElementDescriptor, WatchedElement>;
+type Watches = HashMap<ElementDescriptor, Watch>;
 
 #[derive(Debug)]
 enum WatchedElement {
     Binding(PathBinding),
-    Config(PathBuf)
+    Config(PathBuf),
+    Templates(Pattern)
 }
 
+// A file name in a watched directory, or an empty one for any file of a
+// directory of templates.
 #[derive(Debug, PartialEq, Eq, Hash)]
 struct ElementDescriptor {
     wd: WatchDescriptor,
     file_name: OsString
 }
 
+// The path of a watched file, or of a watched directory of templates, and
+// the indices of the elements it leads to.
+#[derive(Debug)]
+struct Watch {
+    path: PathBuf,
+    elements: Vec<usize>
+}
+
 pub struct Mode {
     pub files: bool,
     pub bindings: bool,
     pub variables: bool
 }
 
+const MAX_LINKS: usize = 40;
+
 
 impl<'a> Watcher<'a> {
-    pub fn new(config_files: ConfigFiles<'a>, mode: Mode) -> Result<Watcher, String> {
+    pub fn new(config_files: ConfigFiles<'a>, mode: Mode, options: Options)
+        -> Result<Watcher<'a>, String>
+    {
         let inotify = match Inotify::init() {
             Ok(i) => i,
             Err(e) => return Err(format!("Couldn't open inotify: {}", e))
@@ -50,8 +69,10 @@ impl<'a> Watcher<'a> {
             config_files,
             inotify,
             generator: None,
+            elements: Vec::new(),
             watches: Watches::new(),
-            mode
+            mode,
+            options
         };
 
         match watcher.update() {
@@ -60,38 +81,77 @@ impl<'a> Watcher<'a> {
         }
     }
 
-    fn add_watch(&mut self, watches: &mut Watches, element: WatchedElement)
-        -> Result<(), String>
-    {
-        let target = match element.target() {
-            Ok(t) => t,
-            Err(e) => return Err(format!(
-                "Couldn't read target {}: {}", element, e))
-        };
-        let dir = target.parent().unwrap();
-        debug!("dir {}", dir.display());
-        match self.inotify.add_watch(dir, WatchMask::CLOSE_WRITE) {
-            Ok(wd) => {
-                let file_name = target.file_name().unwrap().to_owned();
-                let descriptor = ElementDescriptor {
-                    wd,
-                    file_name
+    // Watches the directory of every element and of every symbolic link
+    // leading to it, and stops watching directories no longer needed.
+    //
+    // Directories are watched rather than files, so that files replaced by a
+    // rename, or deleted and created again, are still noticed.
+    fn rewatch(&mut self) {
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO
+            | WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM
+            | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF;
+        let mut watches = Watches::new();
+
+        for (index, element) in self.elements.iter().enumerate() {
+            let locations = match *element {
+                WatchedElement::Templates(ref pattern) => pattern.walk().1
+                    .into_iter()
+                    .map(|dir| (dir.clone(), dir, OsString::new()))
+                    .collect(),
+                _ => element.locations()
+                    .into_iter()
+                    .filter_map(|path| match (path.parent(), path.file_name()) {
+                        (Some(d), Some(f)) => Some((d.to_path_buf(),
+                            path.clone(), f.to_owned())),
+                        _ => None
+                    })
+                    .collect::<Vec<(PathBuf, PathBuf, OsString)>>()
+            };
+            if locations.is_empty() {
+                warn!("Couldn't watch {}: no such directory", element);
+            }
+
+            for (dir, path, file_name) in locations {
+                debug!("dir {}", dir.display());
+                let wd = match self.inotify.add_watch(&dir, mask) {
+                    Ok(wd) => wd,
+                    Err(e) => {
+                        warn!("Couldn't add inotify watch on {}: {}",
+                            dir.display(), e);
+                        continue;
+                    }
                 };
-                watches.insert(descriptor, element);
-            },
-            Err(e) => return Err(format!("Couldn't add inotify watch: {}", e))
-        };
-        Ok(())
+                watches.entry(ElementDescriptor { wd, file_name })
+                    .or_insert_with(|| Watch {
+                        path: path.clone(),
+                        elements: Vec::new()
+                    })
+                    .elements.push(index);
+            }
+        }
+
+        let current: HashSet<&WatchDescriptor> = watches.keys()
+            .map(|d| &d.wd)
+            .collect();
+        let stale: HashSet<&WatchDescriptor> = self.watches.keys()
+            .map(|d| &d.wd)
+            .filter(|wd| !current.contains(wd))
+            .collect();
+        for wd in stale {
+            if let Err(e) = self.inotify.rm_watch(wd.clone()) {
+                debug!("Couldn't remove inotify watch: {}", e);
+            }
+        }
+
+        self.watches = watches;
+        debug!("{:?}", self.watches);
     }
 
     fn update(&mut self) -> Result<(), String> {
 
-        let mut config = match Config::new(&self.config_files) {
-            Ok(c) => c,
-            Err(e) => return Err(e)
-        };
-        let generator = Generator::new(&config.variables);
-        let mut watches = Watches::new();
+        let mut config = Config::new(&self.config_files)?;
+        let generator = Generator::new(
+            &config.variables, &config.literals, self.options.clone())?;
         let mut elements = Vec::new();
 
         if self.mode.files {
@@ -99,6 +159,9 @@ impl<'a> Watcher<'a> {
                 let element = WatchedElement::Binding(binding);
                 elements.push(element);
             }
+            for pattern in config.patterns.drain(..) {
+                elements.push(WatchedElement::Templates(pattern));
+            }
         }
         
         if self.mode.bindings {
@@ -107,22 +170,28 @@ impl<'a> Watcher<'a> {
             elements.push(element);
         }
 
+        // Every layer of variables, the combined config file only once.
+        // Switching profiles changes the variables whatever is watched.
+        let mut sources = Vec::new();
         if self.mode.variables {
-            let element = WatchedElement::Config(
-                self.config_files.variables.to_owned());
-            elements.push(element);
+            sources.append(&mut config.sources);
         }
-
-        while let Some(element) = elements.pop() {
-            if let Err(e) = self.add_watch(&mut watches, element) {
-                return Err(e);
+        if let Some(profile) = self.config_files.profile {
+            sources.push(profile.to_path_buf());
+        }
+        for source in sources {
+            let watched = elements.iter().any(|e| match *e {
+                WatchedElement::Config(ref path) => *path == source,
+                _ => false
+            });
+            if !watched {
+                elements.push(WatchedElement::Config(source));
             }
         }
 
-        self.watches = watches;
+        self.elements = elements;
         self.generator = Some(generator);
-
-        debug!("{:?}", self.watches);
+        self.rewatch();
 
         Ok(())
     }
@@ -147,29 +216,82 @@ impl<'a> Watcher<'a> {
     }
 
     fn handle_event(&mut self, event: Event) -> bool {
-        if let Some(file_name) = event.name {
-            let mut update = false;
-            let descriptor = ElementDescriptor {
-                wd: event.wd,
-                file_name: file_name.to_owned()
-            };
+        // A watched directory went away, watch wherever the files are now.
+        if event.mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF)
+        {
+            self.rewatch();
+            return false;
+        }
 
-            if let Some(element) = self.watches.get(&descriptor) {
-                match element {
-                    &WatchedElement::Binding(ref binding) => self.process(binding),
-                    &WatchedElement::Config(_) => update = true
-                };
+        let file_name = match event.name {
+            Some(f) => f,
+            None => return false
+        };
+        let descriptor = ElementDescriptor {
+            wd: event.wd.clone(),
+            file_name: file_name.to_owned()
+        };
+        let templates = ElementDescriptor {
+            wd: event.wd,
+            file_name: OsString::new()
+        };
+        let (path, elements, in_templates) = match self.watches.get(&descriptor)
+        {
+            Some(w) => (w.path.clone(), w.elements.clone(), false),
+            None => match self.watches.get(&templates) {
+                Some(w) => (w.path.join(file_name), w.elements.clone(), true),
+                None => return false
+            }
+        };
+        let is_link = fs::symlink_metadata(&path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+
+        // Replacing or removing a file may change where the symbolic links
+        // leading to it point. In a directory of templates, only directories,
+        // links and templates change what is watched, not any other file
+        // coming and going.
+        let moved = EventMask::CREATE | EventMask::DELETE
+            | EventMask::MOVED_FROM | EventMask::MOVED_TO;
+        let relevant = !in_templates
+            || is_link
+            || event.mask.contains(EventMask::ISDIR)
+            || elements.iter().any(|&index| match self.elements[index] {
+                WatchedElement::Templates(ref pattern) =>
+                    pattern.matches(&path),
+                _ => false
+            });
+        if event.mask.intersects(moved) && relevant {
+            self.rewatch();
+        }
+
+        // A created file is handled once written, unless it is a link.
+        let changed = event.mask.intersects(
+                EventMask::CLOSE_WRITE | EventMask::MOVED_TO)
+            || (event.mask.contains(EventMask::CREATE) && is_link);
+        if !changed {
+            return false;
+        }
+
+        let mut update = false;
+        for index in elements {
+            match self.elements[index] {
+                WatchedElement::Binding(ref binding) => self.process(binding),
+                WatchedElement::Config(_) => update = true,
+                // A new template, bound once the bindings are read again.
+                WatchedElement::Templates(ref pattern) =>
+                    update |= pattern.matches(&path)
             };
+        }
 
-            if update {
-                match self.update() {
-                    Ok(()) => {
-                        info!("internal configuration updated");
-                        self.process_all();
-                        return true;
-                    },
-                    Err(e) => error!("{}", e)
-                }
+        if update {
+            match self.update() {
+                Ok(()) => {
+                    info!("internal configuration updated");
+                    self.process_all();
+                    return true;
+                },
+                Err(e) => error!("{}", e)
             }
         }
         false
@@ -178,7 +300,7 @@ impl<'a> Watcher<'a> {
     fn process(&self, binding: &PathBinding) {
         if let Some(ref generator) = self.generator {
             match generator.process(binding) {
-                Ok(n) => info!("{}: replaced {} key(s)", binding.from.display(), n),
+                Ok(report) => report.log(binding),
                 Err(e) => error!("{}", e)
             };
         }
@@ -188,8 +310,8 @@ impl<'a> Watcher<'a> {
     }
 
     fn process_all(&self) {
-        for watched_element in self.watches.values() {
-            if let &WatchedElement::Binding(ref binding) = watched_element {
+        for element in self.elements.iter() {
+            if let WatchedElement::Binding(ref binding) = *element {
                 self.process(binding);
             }
         }
@@ -200,15 +322,48 @@ impl<'a> Watcher<'a> {
 impl WatchedElement {
 
     fn source(&self) -> &Path {
-        match self {
-            &WatchedElement::Binding(ref binding) => binding.from.as_path(),
-            &WatchedElement::Config(ref config_file) => config_file.as_path()
+        match *self {
+            WatchedElement::Binding(ref binding) => binding.from.as_path(),
+            WatchedElement::Config(ref config_file) => config_file.as_path(),
+            WatchedElement::Templates(ref pattern) => pattern.base.as_path()
         }
     }
 
     fn target(&self) -> Result<PathBuf, io::Error> {
         self.source().canonicalize()
     }
+
+    // The source and every symbolic link it goes through, each in its
+    // canonical directory. The last one may not exist yet.
+    fn locations(&self) -> Vec<PathBuf> {
+        let mut locations: Vec<PathBuf> = Vec::new();
+        let mut path = self.source().to_path_buf();
+
+        while locations.len() < MAX_LINKS {
+            let dir = match path.parent() {
+                Some(d) if d != Path::new("") => d,
+                _ => Path::new(".")
+            };
+            let location = match (dir.canonicalize(), path.file_name()) {
+                (Ok(dir), Some(file_name)) => dir.join(file_name),
+                _ => break
+            };
+            if locations.contains(&location) {
+                break;
+            }
+
+            let link = fs::read_link(&location);
+            locations.push(location);
+            match link {
+                Ok(target) => path = locations.last().unwrap()
+                    .parent().unwrap()
+                    .join(target),
+                Err(_) => break
+            };
+        }
+
+        locations
+    }
 }
 
 impl fmt::Display for WatchedElement {
@@ -219,3 +374,181 @@ impl fmt::Display for WatchedElement {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempDir;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_locations() {
+        let dir = TempDir::new("locations");
+        fs::create_dir_all(dir.join("dotfiles")).unwrap();
+        fs::create_dir_all(dir.join("config")).unwrap();
+        fs::write(dir.join("dotfiles/a.cfy"), "").unwrap();
+        symlink("../dotfiles/a.cfy", dir.join("config/a.cfy")).unwrap();
+        symlink("missing.cfy", dir.join("config/b.cfy")).unwrap();
+
+        let element = WatchedElement::Config(dir.join("config/./a.cfy"));
+        assert_eq!(element.locations(), vec![
+            dir.join("config/a.cfy"),
+            dir.join("dotfiles/a.cfy")
+        ]);
+
+        let element = WatchedElement::Config(dir.join("config/b.cfy"));
+        assert_eq!(element.locations(), vec![
+            dir.join("config/b.cfy"),
+            dir.join("config/missing.cfy")
+        ]);
+
+        let element = WatchedElement::Config(dir.join("nowhere/c.cfy"));
+        assert!(element.locations().is_empty());
+    }
+
+    // Hands every pending event to the watcher, reading again once it has
+    // been updated as `watch` does.
+    fn drain(watcher: &mut Watcher) {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let events = watcher.inotify.read_events(&mut buffer).unwrap();
+            let mut empty = true;
+            for event in events {
+                empty = false;
+                if watcher.handle_event(event) {
+                    break;
+                }
+            }
+            if empty {
+                return;
+            }
+        }
+    }
+
+    fn watched(watcher: &Watcher, path: &Path) -> bool {
+        watcher.watches.values().any(|w| w.path == path)
+    }
+
+    #[test]
+    fn test_events() {
+        let dir = TempDir::new("events");
+        fs::create_dir_all(dir.join("dotfiles")).unwrap();
+        fs::create_dir_all(dir.join("themes/sub")).unwrap();
+        fs::write(dir.join("dotfiles/a.cfy"), "a ${{font}}").unwrap();
+        fs::write(dir.join("dotfiles/b.cfy"), "b ${{font}}").unwrap();
+        fs::write(dir.join("dotfiles/c.cfy"), "c ${{font}}").unwrap();
+        fs::write(dir.join("themes/sub/d.cfy"), "d ${{font}}").unwrap();
+        symlink("dotfiles/b.cfy", dir.join("link.cfy")).unwrap();
+        let path = dir.join("config.yaml");
+        fs::write(&path, "
+            bindings:
+                - {from: dotfiles/a.cfy, to: a.conf}
+                - {from: link.cfy, to: b.conf}
+                - {from: themes, to: out}
+            variables:
+                font: Hack
+        ").unwrap();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+
+        let mode = Mode { files: true, bindings: true, variables: false };
+        let mut watcher = Watcher::new(ConfigFiles::combined(&path), mode,
+            Options::default()).unwrap();
+        watcher.process_all();
+        assert_eq!(read("a.conf"), "a Hack");
+        assert_eq!(read("b.conf"), "b Hack");
+        assert_eq!(read("out/sub/d"), "d Hack");
+
+        // Saved by renaming a temporary file over it.
+        fs::write(dir.join("dotfiles/a.cfy.tmp"), "a2 ${{font}}").unwrap();
+        fs::rename(dir.join("dotfiles/a.cfy.tmp"), dir.join("dotfiles/a.cfy"))
+            .unwrap();
+        drain(&mut watcher);
+        assert_eq!(read("a.conf"), "a2 Hack");
+
+        // Deleted, then created again.
+        fs::remove_file(dir.join("dotfiles/a.cfy")).unwrap();
+        drain(&mut watcher);
+        fs::write(dir.join("dotfiles/a.cfy"), "a3 ${{font}}").unwrap();
+        drain(&mut watcher);
+        assert_eq!(read("a.conf"), "a3 Hack");
+
+        // A link pointed somewhere else, and the new target then edited.
+        fs::remove_file(dir.join("link.cfy")).unwrap();
+        symlink("dotfiles/c.cfy", dir.join("link.cfy")).unwrap();
+        drain(&mut watcher);
+        assert_eq!(read("b.conf"), "c Hack");
+        fs::write(dir.join("dotfiles/c.cfy"), "c2 ${{font}}").unwrap();
+        drain(&mut watcher);
+        assert_eq!(read("b.conf"), "c2 Hack");
+
+        // The configuration edited.
+        fs::write(&path, "
+            bindings:
+                - {from: dotfiles/a.cfy, to: a.conf}
+                - {from: link.cfy, to: b.conf}
+                - {from: themes, to: out}
+            variables:
+                font: Fira
+        ").unwrap();
+        drain(&mut watcher);
+        assert_eq!(read("a.conf"), "a3 Fira");
+        assert_eq!(read("b.conf"), "c2 Fira");
+
+        // A directory of templates moved, then deleted.
+        assert!(watched(&watcher, &dir.join("themes/sub")));
+        fs::rename(dir.join("themes/sub"), dir.join("themes/moved")).unwrap();
+        drain(&mut watcher);
+        assert!(!watched(&watcher, &dir.join("themes/sub")));
+        fs::write(dir.join("themes/moved/e.cfy"), "e ${{font}}").unwrap();
+        drain(&mut watcher);
+        assert_eq!(read("out/moved/e"), "e Fira");
+        fs::remove_dir_all(dir.join("themes/moved")).unwrap();
+        drain(&mut watcher);
+        assert!(!watched(&watcher, &dir.join("themes/moved")));
+        assert!(watched(&watcher, &dir.join("themes")));
+
+        // Other files coming and going in a directory of templates leave
+        // the watches alone, templates don't.
+        fs::create_dir(dir.join("themes/unseen")).unwrap();
+        watcher.inotify.read_events(&mut [0u8; 4096]).unwrap();
+        fs::write(dir.join("themes/notes.txt"), "").unwrap();
+        fs::remove_file(dir.join("themes/notes.txt")).unwrap();
+        drain(&mut watcher);
+        assert!(!watched(&watcher, &dir.join("themes/unseen")));
+        fs::write(dir.join("themes/f.cfy"), "f ${{font}}").unwrap();
+        drain(&mut watcher);
+        assert!(watched(&watcher, &dir.join("themes/unseen")));
+        assert_eq!(read("out/f"), "f Fira");
+    }
+
+    #[test]
+    fn test_profile_switch() {
+        let dir = TempDir::new("profile-switch");
+        fs::create_dir_all(dir.join("state")).unwrap();
+        fs::write(dir.join("a.cfy"), "a ${{font}}").unwrap();
+        let path = dir.join("config.yaml");
+        fs::write(&path, "
+            bindings: [{from: a.cfy, to: a.conf}]
+            variables:
+                font: Hack
+                profiles: {nord: {font: Iosevka}}
+        ").unwrap();
+        let profile = dir.join("state/profile");
+        let config_files = ConfigFiles {
+            bindings: &path,
+            variables: vec![&path],
+            profile: Some(&profile)
+        };
+        let read = || fs::read_to_string(dir.join("a.conf")).unwrap();
+
+        // Only the templates are watched, the profile still is.
+        let mode = Mode { files: true, bindings: false, variables: false };
+        let mut watcher = Watcher::new(config_files, mode,
+            Options::default()).unwrap();
+        watcher.process_all();
+        assert_eq!(read(), "a Hack");
+        Config::set_profile(&watcher.config_files, "nord").unwrap();
+        drain(&mut watcher);
+        assert_eq!(read(), "a Iosevka");
+    }
+}